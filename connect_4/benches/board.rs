@@ -0,0 +1,116 @@
+//! Compares `Board::score` against the full-board scanner it replaced.
+//!
+//! Both sides play the same pseudo-random games and score the position after every move, which is
+//! the access pattern of the server and of any search built on top of the board.
+
+use connect_4::{Board, GameResult};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+/// The scanner `Board::score` used before the bitboard rewrite, kept here as a baseline.
+#[allow(clippy::needless_range_loop)]
+fn legacy_score(board: &[[i32; 7]; 6]) -> GameResult {
+    let winner = |piece| match piece {
+        1 => GameResult::RedWin,
+        _ => GameResult::YellowWin,
+    };
+    for row in 0..6 {
+        for col in 0..4 {
+            let piece = board[row][col];
+            if piece != 0 && (1..4).all(|i| board[row][col + i] == piece) {
+                return winner(piece);
+            }
+        }
+    }
+    for row in 0..3 {
+        for col in 0..7 {
+            let piece = board[row][col];
+            if piece != 0 && (1..4).all(|i| board[row + i][col] == piece) {
+                return winner(piece);
+            }
+        }
+    }
+    for row in 0..3 {
+        for col in 0..4 {
+            let piece = board[row][col];
+            if piece != 0 && (1..4).all(|i| board[row + i][col + i] == piece) {
+                return winner(piece);
+            }
+        }
+    }
+    for row in 0..3 {
+        for col in 3..7 {
+            let piece = board[row][col];
+            if piece != 0 && (1..4).all(|i| board[row + i][col - i] == piece) {
+                return winner(piece);
+            }
+        }
+    }
+    if board.iter().flatten().any(|&cell| cell == 0) {
+        return GameResult::InProgress;
+    }
+    GameResult::Draw
+}
+
+fn legacy_play(board: &mut [[i32; 7]; 6], col: usize, piece: i32) {
+    for row in (0..6).rev() {
+        if board[row][col] == 0 {
+            board[row][col] = piece;
+            return;
+        }
+    }
+}
+
+/// Plays `count` games of random legal moves until each one is decided.
+fn random_games(count: usize) -> Vec<Vec<u8>> {
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    (0..count)
+        .map(|_| {
+            let mut board = Board::new();
+            let mut moves = Vec::new();
+            while board.score() == GameResult::InProgress {
+                let col = (next() % 7) as u8;
+                if board.play_move(col, board.to_move()).is_ok() {
+                    moves.push(col);
+                }
+            }
+            moves
+        })
+        .collect()
+}
+
+fn bench_score(c: &mut Criterion) {
+    let games = random_games(100);
+    let mut group = c.benchmark_group("play and score");
+    group.bench_function("legacy scanner", |b| {
+        b.iter(|| {
+            for game in &games {
+                let mut board = [[0; 7]; 6];
+                for (i, &col) in game.iter().enumerate() {
+                    legacy_play(&mut board, col as usize, i as i32 % 2 + 1);
+                    black_box(legacy_score(&board));
+                }
+            }
+        })
+    });
+    group.bench_function("bitboard", |b| {
+        b.iter(|| {
+            for game in &games {
+                let mut board = Board::new();
+                for &col in game {
+                    board.play_move(col, board.to_move()).unwrap();
+                    black_box(board.score());
+                }
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_score);
+criterion_main!(benches);
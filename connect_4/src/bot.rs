@@ -0,0 +1,231 @@
+//! Computer opponents for when there is no second human around.
+//!
+//! Every level above `Random` runs a depth-limited negamax with alpha-beta pruning and scores the
+//! leaves by counting lines that are still open for each side. Moves that score the same are
+//! picked at random so that games against the bot do not all look alike.
+
+use crate::{Board, Cell, Color, GameResult, ProtocolError};
+use rand::seq::SliceRandom;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// How strong a bot plays, from random legal moves up to an eight ply search.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Difficulty {
+    Random,
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    /// How many plies the bot looks ahead, or `None` if it does not search at all.
+    pub fn depth(&self) -> Option<u32> {
+        match self {
+            Difficulty::Random => None,
+            Difficulty::Easy => Some(2),
+            Difficulty::Medium => Some(4),
+            Difficulty::Hard => Some(8),
+        }
+    }
+
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            Difficulty::Random => 1,
+            Difficulty::Easy => 2,
+            Difficulty::Medium => 3,
+            Difficulty::Hard => 4,
+        }
+    }
+
+    pub(crate) fn from_byte(value: u8) -> Result<Self, ProtocolError> {
+        match value {
+            1 => Ok(Difficulty::Random),
+            2 => Ok(Difficulty::Easy),
+            3 => Ok(Difficulty::Medium),
+            4 => Ok(Difficulty::Hard),
+            value => Err(ProtocolError::InvalidEnum {
+                field: "difficulty",
+                value,
+            }),
+        }
+    }
+}
+
+impl Display for Difficulty {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difficulty::Random => write!(f, "random"),
+            Difficulty::Easy => write!(f, "easy"),
+            Difficulty::Medium => write!(f, "medium"),
+            Difficulty::Hard => write!(f, "hard"),
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "random" => Ok(Difficulty::Random),
+            "easy" => Ok(Difficulty::Easy),
+            "medium" => Ok(Difficulty::Medium),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(format!("unknown difficulty {:?}", s.trim())),
+        }
+    }
+}
+
+/// Scores are from the point of view of the side to move. Wins are worth more the sooner they
+/// happen, and always outweigh any heuristic score.
+const WIN: i32 = 1_000_000;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Bot {
+    difficulty: Difficulty,
+}
+
+impl Bot {
+    pub fn new(difficulty: Difficulty) -> Self {
+        Bot { difficulty }
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    /// Picks a column for `color` to play, or `None` if the board is full.
+    pub fn choose_move(&self, board: &Board, color: Color) -> Option<u8> {
+        let legal: Vec<u8> = (0..board.config().cols())
+            .filter(|&col| board.legal_move(col).is_ok())
+            .collect();
+        let depth = match self.difficulty.depth() {
+            Some(depth) => depth,
+            None => return legal.choose(&mut rand::thread_rng()).copied(),
+        };
+
+        let mut best = Vec::new();
+        let mut best_score = i32::MIN;
+        for col in legal {
+            let mut child = board.clone();
+            child.play_move(col, color).unwrap();
+            let score = -negamax(&child, color.opponent(), depth - 1, -WIN * 2, WIN * 2);
+            if score > best_score {
+                best_score = score;
+                best.clear();
+            }
+            if score == best_score {
+                best.push(col);
+            }
+        }
+        best.choose(&mut rand::thread_rng()).copied()
+    }
+}
+
+/// Scores `board` for `color`, which is about to move, looking `depth` plies ahead.
+fn negamax(board: &Board, color: Color, depth: u32, mut alpha: i32, beta: i32) -> i32 {
+    match board.score() {
+        // the previous move won, so the side to move lost
+        GameResult::RedWin | GameResult::YellowWin => return -(WIN + depth as i32),
+        GameResult::Draw => return 0,
+        GameResult::InProgress => {}
+    }
+    if depth == 0 {
+        return heuristic(board, color);
+    }
+    let cols = board.config().cols();
+    let mut best = i32::MIN;
+    // center columns first, they are usually the strongest and prune the most
+    let mut order: Vec<u8> = (0..cols).collect();
+    order.sort_by_key(|&col| (2 * col as i32 - (cols as i32 - 1)).abs());
+    for col in order {
+        let mut child = board.clone();
+        if child.play_move(col, color).is_err() {
+            continue;
+        }
+        let score = -negamax(&child, color.opponent(), depth - 1, -beta, -alpha);
+        best = best.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+    best
+}
+
+/// Counts every window of `connect` cells that only one side has pieces in, weighting windows
+/// that are closer to complete much more heavily.
+fn heuristic(board: &Board, color: Color) -> i32 {
+    let config = board.config();
+    let (rows, cols, connect) = (
+        config.rows() as isize,
+        config.cols() as isize,
+        config.connect() as isize,
+    );
+    let mut score = 0;
+    for row in 0..rows {
+        for col in 0..cols {
+            for (dr, dc) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+                let end = (row + dr * (connect - 1), col + dc * (connect - 1));
+                if !(0..rows).contains(&end.0) || !(0..cols).contains(&end.1) {
+                    continue;
+                }
+                let (mut mine, mut theirs) = (0, 0);
+                for i in 0..connect {
+                    match board.cell((row + dr * i) as u8, (col + dc * i) as u8) {
+                        Some(Cell::Piece(piece)) if piece == color => mine += 1,
+                        Some(Cell::Piece(_)) => theirs += 1,
+                        _ => {}
+                    }
+                }
+                // capped so that long connect lengths cannot overflow the score
+                if theirs == 0 && mine > 0 {
+                    score += 1 << (2 * mine).min(20);
+                } else if mine == 0 && theirs > 0 {
+                    score -= 1 << (2 * theirs).min(20);
+                }
+            }
+        }
+    }
+    score
+}
+
+#[test]
+fn test_bot_moves() {
+    use crate::BoardConfig;
+
+    let play = |moves: &[u8]| {
+        let mut board = Board::new();
+        for &col in moves {
+            board.play_move(col, board.to_move()).unwrap();
+        }
+        board
+    };
+    // red threatens to win in column 3, so every searching bot blocks it
+    let board = play(&[3, 0, 3, 0, 3]);
+    for difficulty in [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard] {
+        let bot = Bot::new(difficulty);
+        assert_eq!(bot.choose_move(&board, Color::Yellow), Some(3));
+        // and takes a win when there is one
+        assert_eq!(bot.choose_move(&board, Color::Red), Some(3));
+    }
+    // the random bot only ever plays legal moves
+    let mut board = Board::with_config(BoardConfig::new(2, 2, 2).unwrap());
+    board.play_move(0, Color::Red).unwrap();
+    board.play_move(0, Color::Yellow).unwrap();
+    for _ in 0..10 {
+        assert_eq!(
+            Bot::new(Difficulty::Random).choose_move(&board, Color::Red),
+            Some(1)
+        );
+    }
+    // and nobody can move once the game is over
+    board.play_move(1, Color::Red).unwrap();
+    assert_eq!(
+        Bot::new(Difficulty::Hard).choose_move(&board, Color::Red),
+        None
+    );
+}
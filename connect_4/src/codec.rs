@@ -0,0 +1,479 @@
+//! Framing and typed packet streams over any async byte stream.
+//!
+//! Every packet travels as one frame: a big-endian `u32` length followed by the serialized packet.
+//! `PacketReader` and `PacketWriter` wrap anything that implements `AsyncRead` or `AsyncWrite`, so
+//! the same code runs over TCP, Unix sockets, TLS or an in-memory `tokio::io::duplex`, and their
+//! packet type parameter keeps a client from sending a clientbound packet by mistake.
+//!
+//! With the `serde` feature, `JsonReader` and `JsonWriter` speak the same packets as one JSON
+//! object per line instead, for tools that would rather not deal with the binary layout.
+
+use crate::{Deserialize, ProtocolError, Serialize};
+use std::error::Error;
+use std::fmt::Debug;
+use std::io;
+use std::marker::PhantomData;
+#[cfg(feature = "serde")]
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+
+/// The largest frame `read_frame` accepts unless told otherwise, far more than any packet needs.
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 64 * 1024;
+
+/// Reads one frame, a big-endian `u32` length followed by that many bytes, as written by
+/// `send_packet`. A length over `max_size` is refused before anything is allocated for it, since
+/// the peer could otherwise make us reserve up to 4 GiB.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    max_size: u32,
+) -> io::Result<Vec<u8>> {
+    let len = stream.read_u32().await?;
+    if len > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is over the limit of {}", len, max_size),
+        ));
+    }
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Writes `buf` as one frame.
+async fn write_frame<W: AsyncWrite + Unpin>(stream: &mut W, buf: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(buf.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "packet is too large to frame"))?;
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(buf).await
+}
+
+/// Sends a single packet without wrapping `stream` in a `PacketWriter`, such as an answer to a
+/// handshake.
+pub async fn send_packet<T: Serialize + Debug, W: AsyncWrite + Unpin>(
+    packet: T,
+    stream: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    println!("sending packet: {:?}", packet);
+    write_frame(stream, &packet.serialize()).await?;
+    Ok(())
+}
+
+/// Whether a client whose first byte is `first_byte` frames its packets the way version 1 did,
+/// with a single length byte. The first byte of a `u32` length can only be nonzero for frames too
+/// long for `max_size`, so those are never mistaken for anything the current framing accepts.
+pub fn is_legacy_frame(first_byte: u8, max_size: u32) -> bool {
+    u32::from(first_byte) << 24 > max_size
+}
+
+/// Reads one frame from a version 1 client, a length byte followed by that many bytes.
+pub async fn read_legacy_frame<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Vec<u8>> {
+    let len = stream.read_u8().await?;
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Sends a single packet framed for a version 1 client, which is only ever told to update.
+pub async fn send_legacy_packet<T: Serialize + Debug, W: AsyncWrite + Unpin>(
+    packet: T,
+    stream: &mut W,
+) -> io::Result<()> {
+    println!("sending packet: {:?}", packet);
+    let buf = packet.serialize();
+    let len = u8::try_from(buf.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "packet is too large for a version 1 frame",
+        )
+    })?;
+    stream.write_all(&[len]).await?;
+    stream.write_all(&buf).await
+}
+
+/// Reads packets of type `P` from `R`, one frame at a time.
+pub struct PacketReader<R, P> {
+    inner: R,
+    max_frame_size: u32,
+    packets: PhantomData<fn() -> P>,
+}
+
+impl<R: AsyncRead + Unpin, P: Deserialize> PacketReader<R, P> {
+    pub fn new(inner: R) -> Self {
+        Self::with_max_frame_size(inner, DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    /// Refuses frames longer than `max_frame_size` bytes, see `read_frame`.
+    pub fn with_max_frame_size(inner: R, max_frame_size: u32) -> Self {
+        PacketReader {
+            inner,
+            max_frame_size,
+            packets: PhantomData,
+        }
+    }
+
+    /// Reads the next packet. The outer error means the stream is gone, either closed or broken,
+    /// while the inner one means the peer sent bytes that are not a valid packet. A malformed
+    /// packet still uses up exactly its frame, so reading can carry on after one.
+    pub async fn read(&mut self) -> io::Result<Result<P, ProtocolError>> {
+        let buf = read_frame(&mut self.inner, self.max_frame_size).await?;
+        Ok(P::deserialize(&buf))
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// Writes packets of type `P` to `W`, one frame each.
+pub struct PacketWriter<W, P> {
+    inner: W,
+    packets: PhantomData<fn(P)>,
+}
+
+impl<W: AsyncWrite + Unpin, P: Serialize + Debug> PacketWriter<W, P> {
+    pub fn new(inner: W) -> Self {
+        PacketWriter {
+            inner,
+            packets: PhantomData,
+        }
+    }
+
+    pub async fn send(&mut self, packet: P) -> io::Result<()> {
+        println!("sending packet: {:?}", packet);
+        write_frame(&mut self.inner, &packet.serialize()).await
+    }
+
+    /// Flushes what was sent and closes the writing side, so that the peer reads to the end of
+    /// the stream. Dropping a writer made by `split` does not do this by itself.
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        self.inner.shutdown().await
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Splits a two-way stream into a reader of `In` packets and a writer of `Out` packets, so that
+/// each half can live on its own task.
+pub fn split<S, In, Out>(
+    stream: S,
+    max_frame_size: u32,
+) -> (
+    PacketReader<ReadHalf<S>, In>,
+    PacketWriter<WriteHalf<S>, Out>,
+)
+where
+    S: AsyncRead + AsyncWrite,
+    In: Deserialize,
+    Out: Serialize + Debug,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    (
+        PacketReader::with_max_frame_size(reader, max_frame_size),
+        PacketWriter::new(writer),
+    )
+}
+
+/// Reads packets of type `P` from `R`, one JSON object per line. Blank lines are skipped, so a
+/// person typing into a raw socket can press enter freely.
+#[cfg(feature = "serde")]
+pub struct JsonReader<R, P> {
+    inner: BufReader<R>,
+    max_line_size: u32,
+    packets: PhantomData<fn() -> P>,
+}
+
+#[cfg(feature = "serde")]
+impl<R: AsyncRead + Unpin, P: serde::de::DeserializeOwned> JsonReader<R, P> {
+    /// Refuses lines longer than `max_line_size` bytes, counting the newline, like
+    /// `PacketReader::with_max_frame_size`.
+    pub fn new(inner: R, max_line_size: u32) -> Self {
+        JsonReader {
+            inner: BufReader::new(inner),
+            max_line_size,
+            packets: PhantomData,
+        }
+    }
+
+    /// Reads the next packet, with the same two layers of errors as `PacketReader::read`.
+    pub async fn read(&mut self) -> io::Result<Result<P, ProtocolError>> {
+        let mut line = Vec::new();
+        while line.iter().all(u8::is_ascii_whitespace) {
+            line.clear();
+            // one byte over the limit is enough to tell that the line is too long
+            let mut limited = (&mut self.inner).take(self.max_line_size as u64 + 1);
+            if limited.read_until(b'\n', &mut line).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            if line.len() > self.max_line_size as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line is over the limit of {} bytes", self.max_line_size),
+                ));
+            }
+        }
+        Ok(serde_json::from_slice(&line).map_err(|e| ProtocolError::InvalidJson(e.to_string())))
+    }
+}
+
+/// Writes packets of type `P` to `W`, one JSON object per line.
+#[cfg(feature = "serde")]
+pub struct JsonWriter<W, P> {
+    inner: W,
+    packets: PhantomData<fn(P)>,
+}
+
+#[cfg(feature = "serde")]
+impl<W: AsyncWrite + Unpin, P: serde::Serialize + Debug> JsonWriter<W, P> {
+    pub fn new(inner: W) -> Self {
+        JsonWriter {
+            inner,
+            packets: PhantomData,
+        }
+    }
+
+    pub async fn send(&mut self, packet: P) -> io::Result<()> {
+        println!("sending packet: {:?}", packet);
+        let mut line = serde_json::to_vec(&packet)?;
+        line.push(b'\n');
+        self.inner.write_all(&line).await
+    }
+
+    /// Like `PacketWriter::shutdown`.
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        self.inner.shutdown().await
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+/// Like `split`, for the JSON protocol.
+#[cfg(feature = "serde")]
+pub fn split_json<S, In, Out>(
+    stream: S,
+    max_line_size: u32,
+) -> (JsonReader<ReadHalf<S>, In>, JsonWriter<WriteHalf<S>, Out>)
+where
+    S: AsyncRead + AsyncWrite,
+    In: serde::de::DeserializeOwned,
+    Out: serde::Serialize + Debug,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    (
+        JsonReader::new(reader, max_line_size),
+        JsonWriter::new(writer),
+    )
+}
+
+#[cfg(test)]
+use crate::{ClientBoundPacket, ErrorCode, ServerBoundPacket};
+
+#[tokio::test]
+async fn test_framing() {
+    // a packet longer than a u8 length could describe
+    let packet = ClientBoundPacket::Error {
+        code: ErrorCode::MalformedPacket,
+        message: "x".repeat(200),
+    };
+    let mut stream = Vec::new();
+    send_packet(packet.clone(), &mut stream).await.unwrap();
+    send_packet(ServerBoundPacket::Forfeit, &mut stream)
+        .await
+        .unwrap();
+    assert_eq!(&stream[..4], &[0, 0, 0, 202]);
+
+    let mut reader = &stream[..];
+    let buf = read_frame(&mut reader, DEFAULT_MAX_FRAME_SIZE)
+        .await
+        .unwrap();
+    assert_eq!(ClientBoundPacket::deserialize(&buf), Ok(packet));
+    let buf = read_frame(&mut reader, DEFAULT_MAX_FRAME_SIZE)
+        .await
+        .unwrap();
+    assert_eq!(
+        ServerBoundPacket::deserialize(&buf),
+        Ok(ServerBoundPacket::Forfeit)
+    );
+    assert_eq!(
+        read_frame(&mut reader, DEFAULT_MAX_FRAME_SIZE)
+            .await
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+
+    // the length alone is enough to refuse a frame, whatever follows it
+    let mut reader = &[0xff, 0xff, 0xff, 0xff][..];
+    assert_eq!(
+        read_frame(&mut reader, DEFAULT_MAX_FRAME_SIZE)
+            .await
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::InvalidData
+    );
+    let mut reader = &stream[..];
+    assert!(read_frame(&mut reader, 100).await.is_err());
+}
+
+#[tokio::test]
+async fn test_legacy_frames() {
+    // a version 1 `Init`, which had no magic or version
+    let mut stream = &[5, 0, b'o', b'l', b'd', b'y'][..];
+    assert!(is_legacy_frame(stream[0], DEFAULT_MAX_FRAME_SIZE));
+    assert_eq!(read_legacy_frame(&mut stream).await.unwrap(), b"\0oldy");
+    // nor is any current frame taken for one, unless the limit is raised past 16 MiB
+    assert!(!is_legacy_frame(0, DEFAULT_MAX_FRAME_SIZE));
+    assert!(!is_legacy_frame(1, u32::MAX));
+
+    let mut stream = Vec::new();
+    send_legacy_packet(ServerBoundPacket::Forfeit, &mut stream)
+        .await
+        .unwrap();
+    assert_eq!(stream, [1, 2]);
+    let game = crate::GameSummary {
+        id: 1,
+        red: "r".repeat(32),
+        yellow: "y".repeat(32),
+    };
+    let packet = ClientBoundPacket::GameList {
+        games: vec![game; 4],
+    };
+    assert!(send_legacy_packet(packet, &mut Vec::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_packet_streams_over_duplex() {
+    use crate::{Capabilities, PROTOCOL_VERSION};
+
+    let (client, server) = tokio::io::duplex(16);
+    let (mut client_reader, mut client_writer) =
+        split::<_, ClientBoundPacket, ServerBoundPacket>(client, DEFAULT_MAX_FRAME_SIZE);
+    let (mut server_reader, mut server_writer) =
+        split::<_, ServerBoundPacket, ClientBoundPacket>(server, DEFAULT_MAX_FRAME_SIZE);
+
+    // longer than the duplex buffer, so the server has to read while the client is writing
+    let init = ServerBoundPacket::Init {
+        name: "n".repeat(32),
+        bot: None,
+        version: PROTOCOL_VERSION,
+        capabilities: Capabilities::CHAT,
+    };
+    let server = tokio::spawn(async move {
+        let packet = server_reader.read().await.unwrap().unwrap();
+        let ServerBoundPacket::Init { capabilities, .. } = packet else {
+            panic!("expected init, got {:?}", packet);
+        };
+        server_writer
+            .send(ClientBoundPacket::Welcome {
+                version: PROTOCOL_VERSION,
+                capabilities: capabilities.intersection(Capabilities::VARIANTS),
+            })
+            .await
+            .unwrap();
+        // a frame that is not a packet is reported without losing the stream
+        assert_eq!(
+            server_reader.read().await.unwrap(),
+            Err(ProtocolError::UnknownOpcode(200))
+        );
+        assert_eq!(
+            server_reader.read().await.unwrap(),
+            Ok(ServerBoundPacket::Forfeit)
+        );
+        // and the client hanging up ends the stream
+        assert!(server_reader.read().await.is_err());
+    });
+
+    client_writer.send(init).await.unwrap();
+    assert_eq!(
+        client_reader.read().await.unwrap(),
+        Ok(ClientBoundPacket::Welcome {
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::NONE,
+        })
+    );
+    let mut raw = client_writer.into_inner();
+    write_frame(&mut raw, &[200]).await.unwrap();
+    let mut client_writer = PacketWriter::new(raw);
+    client_writer
+        .send(ServerBoundPacket::Forfeit)
+        .await
+        .unwrap();
+    // shutting down is enough for the server to see the end, even with our reader still around
+    client_writer.shutdown().await.unwrap();
+    server.await.unwrap();
+    drop(client_reader);
+}
+
+#[cfg(feature = "serde")]
+#[tokio::test]
+async fn test_json_streams() {
+    use crate::{Board, BoardConfig, Color};
+
+    let (client, server) = tokio::io::duplex(1024);
+    let (mut client_reader, mut client_writer) =
+        split_json::<_, ClientBoundPacket, ServerBoundPacket>(client, 4096);
+    let (mut server_reader, mut server_writer) =
+        split_json::<_, ServerBoundPacket, ClientBoundPacket>(server, 200);
+
+    // what a script would type by hand
+    client_writer
+        .get_mut()
+        .write_all(b"\n{\"type\": \"move\", \"col\": 3}\nnot json\n")
+        .await
+        .unwrap();
+    assert_eq!(
+        server_reader.read().await.unwrap(),
+        Ok(ServerBoundPacket::Move { col: 3 })
+    );
+    assert!(matches!(
+        server_reader.read().await.unwrap(),
+        Err(ProtocolError::InvalidJson(_))
+    ));
+    client_writer
+        .send(ServerBoundPacket::Forfeit)
+        .await
+        .unwrap();
+    assert_eq!(
+        server_reader.read().await.unwrap(),
+        Ok(ServerBoundPacket::Forfeit)
+    );
+
+    let board = Board::from_moves("4453").unwrap();
+    let state = ClientBoundPacket::State {
+        red: "a".to_string(),
+        yellow: "b".to_string(),
+        config: BoardConfig::STANDARD,
+        cells: board.cells(),
+        to_move: Color::Red,
+        move_number: 4,
+    };
+    server_writer.send(state.clone()).await.unwrap();
+    server_writer.shutdown().await.unwrap();
+    assert_eq!(client_reader.read().await.unwrap(), Ok(state));
+    assert_eq!(
+        client_reader.read().await.unwrap_err().kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+
+    // a line with no end in sight is refused once it passes the limit
+    client_writer
+        .get_mut()
+        .write_all(&[b' '; 300])
+        .await
+        .unwrap();
+    assert_eq!(
+        server_reader.read().await.unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+}
@@ -0,0 +1,261 @@
+//! The rules of a single game, shared by the server and the client.
+//!
+//! `Game` wraps a `Board` with whose turn it is and how the game ended, and refuses any move that
+//! would break the rules instead of trusting whoever sent it.
+
+use crate::{Board, BoardConfig, Color, EndReason, ErrorCode, GameResult, MoveError};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Reasons a move was rejected by `Game::apply`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GameError {
+    NotYourTurn,
+    /// The game has already been decided.
+    GameOver,
+    IllegalMove(MoveError),
+}
+
+impl GameError {
+    /// The code a server sends back to the player whose move was rejected.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            GameError::NotYourTurn => ErrorCode::NotYourTurn,
+            GameError::GameOver | GameError::IllegalMove(MoveError::GameOver) => {
+                ErrorCode::GameOver
+            }
+            GameError::IllegalMove(_) => ErrorCode::IllegalMove,
+        }
+    }
+}
+
+impl Display for GameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameError::NotYourTurn => write!(f, "it is not your turn"),
+            GameError::GameOver => write!(f, "the game is already over"),
+            GameError::IllegalMove(e) => write!(f, "illegal move: {}", e),
+        }
+    }
+}
+
+impl Error for GameError {}
+
+#[derive(Debug, Clone)]
+pub struct Game {
+    board: Board,
+    turn: Color,
+    result: GameResult,
+    /// How the game ended, once it has.
+    reason: Option<EndReason>,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new(BoardConfig::STANDARD)
+    }
+}
+
+impl Game {
+    /// Starts a game on an empty board. Red moves first.
+    pub fn new(config: BoardConfig) -> Self {
+        Game {
+            board: Board::with_config(config),
+            turn: Color::Red,
+            result: GameResult::InProgress,
+            reason: None,
+        }
+    }
+
+    /// Rebuilds a game from every column played so far, red's first, checking each move.
+    pub fn replay(config: BoardConfig, moves: &[u8]) -> Result<Self, GameError> {
+        let mut game = Self::new(config);
+        for &col in moves {
+            game.apply(game.turn(), col)?;
+        }
+        Ok(game)
+    }
+
+    /// Picks a game up from a position, such as one rebuilt from a server's `State`. A game over
+    /// the board is taken to have been completed there.
+    pub fn from_board(board: Board) -> Self {
+        let result = board.score();
+        let (turn, reason) = match result {
+            GameResult::InProgress => (board.to_move(), None),
+            _ => (board.to_move().opponent(), Some(EndReason::Completed)),
+        };
+        Game {
+            board,
+            turn,
+            result,
+            reason,
+        }
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn config(&self) -> BoardConfig {
+        self.board.config()
+    }
+
+    /// Whose move it is. Stays on the last mover once the game is over.
+    pub fn turn(&self) -> Color {
+        self.turn
+    }
+
+    /// Every column played so far, in order.
+    pub fn moves(&self) -> &[u8] {
+        self.board.moves()
+    }
+
+    pub fn result(&self) -> GameResult {
+        self.result.clone()
+    }
+
+    pub fn end_reason(&self) -> Option<EndReason> {
+        self.reason
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.result != GameResult::InProgress
+    }
+
+    /// Checks whether `color` may play in `col` right now without changing anything.
+    pub fn check(&self, color: Color, col: u8) -> Result<(), GameError> {
+        if self.is_over() {
+            return Err(GameError::GameOver);
+        }
+        if color != self.turn {
+            return Err(GameError::NotYourTurn);
+        }
+        self.board.legal_move(col).map_err(GameError::IllegalMove)
+    }
+
+    /// Plays `color`'s piece in `col` and returns the result of the game after it.
+    pub fn apply(&mut self, color: Color, col: u8) -> Result<GameResult, GameError> {
+        self.check(color, col)?;
+        self.board
+            .play_move(col, color)
+            .map_err(GameError::IllegalMove)?;
+        self.result = self.board.score();
+        if self.is_over() {
+            self.reason = Some(EndReason::Completed);
+        } else {
+            self.turn = color.opponent();
+        }
+        Ok(self.result())
+    }
+
+    /// Ends the game with a win for `color`'s opponent. Either player may forfeit at any time,
+    /// not only on their turn.
+    pub fn forfeit(&mut self, color: Color) -> Result<GameResult, GameError> {
+        self.concede(color, EndReason::Forfeit)
+    }
+
+    /// Ends the game with a win for `color`'s opponent because `color`'s connection was lost.
+    pub fn abandon(&mut self, color: Color) -> Result<GameResult, GameError> {
+        self.concede(color, EndReason::Disconnected)
+    }
+
+    fn concede(&mut self, color: Color, reason: EndReason) -> Result<GameResult, GameError> {
+        if self.is_over() {
+            return Err(GameError::GameOver);
+        }
+        self.result = match color {
+            Color::Red => GameResult::YellowWin,
+            Color::Yellow => GameResult::RedWin,
+        };
+        self.reason = Some(reason);
+        Ok(self.result())
+    }
+}
+
+#[test]
+fn test_game_rules() {
+    let mut game = Game::default();
+    assert_eq!(game.turn(), Color::Red);
+    assert_eq!(game.apply(Color::Yellow, 3), Err(GameError::NotYourTurn));
+    assert_eq!(GameError::NotYourTurn.code(), ErrorCode::NotYourTurn);
+    assert_eq!(
+        game.apply(Color::Red, 9),
+        Err(GameError::IllegalMove(MoveError::OutOfRange))
+    );
+    assert!(game.moves().is_empty());
+
+    for _ in 0..3 {
+        assert_eq!(game.apply(Color::Red, 3), Ok(GameResult::InProgress));
+        assert_eq!(game.apply(Color::Yellow, 3), Ok(GameResult::InProgress));
+    }
+    assert_eq!(
+        game.apply(Color::Red, 3),
+        Err(GameError::IllegalMove(MoveError::ColumnFull))
+    );
+    assert_eq!(game.turn(), Color::Red);
+
+    for col in [4, 4, 5, 5] {
+        game.apply(game.turn(), col).unwrap();
+    }
+    assert_eq!(game.check(Color::Red, 6), Ok(()));
+    assert_eq!(game.apply(Color::Red, 6), Ok(GameResult::RedWin));
+    assert!(game.is_over());
+    assert_eq!(game.turn(), Color::Red);
+    assert_eq!(game.apply(Color::Yellow, 0), Err(GameError::GameOver));
+    assert_eq!(GameError::GameOver.code(), ErrorCode::GameOver);
+    assert_eq!(game.moves(), &[3, 3, 3, 3, 3, 3, 4, 4, 5, 5, 6]);
+    assert_eq!(game.board().score(), GameResult::RedWin);
+    assert_eq!(game.end_reason(), Some(EndReason::Completed));
+    assert_eq!(game.forfeit(Color::Red), Err(GameError::GameOver));
+}
+
+#[test]
+fn test_game_replay() {
+    let game = Game::replay(BoardConfig::STANDARD, &[3, 3, 4, 4, 5, 5, 6]).unwrap();
+    assert_eq!(game.result(), GameResult::RedWin);
+    assert_eq!(game.moves(), &[3, 3, 4, 4, 5, 5, 6]);
+
+    let game = Game::replay(BoardConfig::STANDARD, &[3, 2]).unwrap();
+    assert_eq!(game.turn(), Color::Red);
+    assert_eq!(
+        Game::replay(BoardConfig::STANDARD, &[3, 3, 4, 4, 5, 5, 6, 0]).unwrap_err(),
+        GameError::GameOver
+    );
+    assert_eq!(
+        Game::replay(BoardConfig::STANDARD, &[7]).unwrap_err(),
+        GameError::IllegalMove(MoveError::OutOfRange)
+    );
+}
+
+#[test]
+fn test_game_from_board() {
+    let board = Board::from_moves("445").unwrap();
+    let mut game = Game::from_board(Board::from_cells(board.config(), &board.cells()).unwrap());
+    assert_eq!(game.turn(), Color::Yellow);
+    assert_eq!(game.apply(Color::Red, 3), Err(GameError::NotYourTurn));
+    assert_eq!(game.apply(Color::Yellow, 3), Ok(GameResult::InProgress));
+
+    let game = Game::from_board(Board::from_moves("1122334").unwrap());
+    assert_eq!(game.result(), GameResult::RedWin);
+    assert_eq!(game.turn(), Color::Red);
+    assert_eq!(game.end_reason(), Some(EndReason::Completed));
+}
+
+#[test]
+fn test_game_forfeit() {
+    let mut game = Game::default();
+    game.apply(Color::Red, 3).unwrap();
+    assert_eq!(game.end_reason(), None);
+    // red can resign while it is yellow's turn
+    assert_eq!(game.forfeit(Color::Red), Ok(GameResult::YellowWin));
+    assert!(game.is_over());
+    assert_eq!(game.end_reason(), Some(EndReason::Forfeit));
+    assert_eq!(game.board().score(), GameResult::InProgress);
+    assert_eq!(game.apply(Color::Yellow, 3), Err(GameError::GameOver));
+    assert_eq!(game.forfeit(Color::Yellow), Err(GameError::GameOver));
+
+    let mut game = Game::default();
+    assert_eq!(game.abandon(Color::Yellow), Ok(GameResult::RedWin));
+    assert_eq!(game.end_reason(), Some(EndReason::Disconnected));
+    assert_eq!(game.abandon(Color::Red), Err(GameError::GameOver));
+}
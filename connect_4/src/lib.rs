@@ -1,90 +1,615 @@
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
-
-pub async fn send_packet<T: Serialize + Debug>(
-    packet: T,
-    stream: &mut TcpStream,
-) -> Result<(), Box<dyn Error>> {
-    // check if it is serverbound or clientbound
-    println!("sending packet: {:?}", packet);
-    // write to temporary buf
-    let buf = packet.serialize();
-    // write length of buf
-    stream.write_all(&[buf.len() as u8]).await?;
-    // write buf
-    stream.write_all(&buf).await?;
-    Ok(())
+use std::ops::BitOr;
+
+pub mod bot;
+pub mod codec;
+pub mod game;
+pub mod solver;
+
+use bot::Difficulty;
+pub use codec::{read_frame, send_packet, DEFAULT_MAX_FRAME_SIZE};
+
+/// Opens every `Init`, so that a server can tell a client of this protocol apart from anything
+/// else that connects, including clients from before the protocol was versioned.
+pub const PROTOCOL_MAGIC: [u8; 4] = *b"C4MP";
+
+/// The version of the packet layouts in this crate. Bumped whenever one of them changes.
+///
+/// Version 2 moved from `u8` to `u32` frame lengths, version 3 added the matchmaking queue, and
+/// version 4 added private rooms.
+pub const PROTOCOL_VERSION: u8 = 4;
+
+/// The oldest version this crate still decodes, so that a server can be upgraded before all of
+/// its clients are. Version 1 clients frame packets differently, so they cannot be read at all.
+pub const MIN_PROTOCOL_VERSION: u8 = 2;
+
+/// The longest chat message, in bytes, that a server passes on.
+pub const MAX_CHAT_LEN: usize = 200;
+
+/// Optional features a peer supports. The client offers its set in `Init` and the server answers
+/// with the ones both sides have in `Welcome`, so features can be added without a new version.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Capabilities(u8);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    pub const CHAT: Capabilities = Capabilities(1);
+    pub const SPECTATE: Capabilities = Capabilities(1 << 1);
+    // 1 << 2 is free for the next feature that needs a bit
+    /// Boards other than the standard 6x7 connect 4.
+    pub const VARIANTS: Capabilities = Capabilities(1 << 3);
+
+    /// Keeps bits this version does not know about, since they may mean something to the peer.
+    pub fn from_bits(bits: u8) -> Self {
+        Capabilities(bits)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The features both `self` and `other` support.
+    pub fn intersection(self, other: Capabilities) -> Self {
+        Capabilities(self.0 & other.0)
+    }
+
+    /// The features either `self` or `other` supports. The same as `|`, but usable in constants.
+    pub const fn union(self, other: Capabilities) -> Self {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        self.union(other)
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let names = [
+            (Capabilities::CHAT, "chat"),
+            (Capabilities::SPECTATE, "spectate"),
+            (Capabilities::VARIANTS, "variants"),
+        ]
+        .into_iter()
+        .filter(|&(capability, _)| self.contains(capability))
+        .map(|(_, name)| name)
+        .collect::<Vec<_>>();
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(", "))
+        }
+    }
 }
 
 pub trait Serialize {
     fn serialize(&self) -> Vec<u8>;
 }
 
-pub trait Deserialize {
-    fn deserialize(buf: &[u8]) -> Self;
+pub trait Deserialize: Sized {
+    fn deserialize(buf: &[u8]) -> Result<Self, ProtocolError>;
+}
+
+/// Writes session tokens as strings in JSON, since JavaScript numbers cannot hold all 64 bits.
+#[cfg(feature = "serde")]
+mod token_string {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(token: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(token)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Reasons a packet could not be decoded from the bytes a peer sent us.
+#[derive(PartialEq, Debug)]
+pub enum ProtocolError {
+    /// The first byte of the packet does not name a known packet type.
+    UnknownOpcode(u8),
+    /// The packet ended before all of its fields were read.
+    Truncated,
+    /// A byte that should encode an enum (color, game result, ...) is out of range.
+    InvalidEnum { field: &'static str, value: u8 },
+    /// A string field is not valid UTF-8.
+    InvalidUtf8,
+    /// The board dimensions or connect length do not describe a playable board.
+    InvalidConfig,
+    /// A line of the JSON protocol is not a packet, with serde's description of why.
+    InvalidJson(String),
+    /// An `Init` does not start with `PROTOCOL_MAGIC`, so it comes from something that does not
+    /// speak this protocol or from a client older than its versioning.
+    BadMagic,
+    /// An `Init` is for a protocol version this crate cannot decode.
+    UnsupportedVersion(u8),
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::UnknownOpcode(opcode) => write!(f, "unknown packet type {}", opcode),
+            ProtocolError::Truncated => write!(f, "packet is truncated"),
+            ProtocolError::InvalidEnum { field, value } => {
+                write!(f, "invalid value {} for {}", value, field)
+            }
+            ProtocolError::InvalidUtf8 => write!(f, "string is not valid utf-8"),
+            ProtocolError::InvalidConfig => write!(f, "invalid board config"),
+            ProtocolError::InvalidJson(e) => write!(f, "invalid json: {}", e),
+            ProtocolError::BadMagic => write!(f, "missing protocol magic"),
+            ProtocolError::UnsupportedVersion(version) => write!(
+                f,
+                "protocol version {} is not supported (expected {} to {})",
+                version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+fn read_byte(buf: &[u8], index: usize) -> Result<u8, ProtocolError> {
+    buf.get(index).copied().ok_or(ProtocolError::Truncated)
+}
+
+fn read_string(buf: &[u8]) -> Result<String, ProtocolError> {
+    String::from_utf8(buf.to_vec()).map_err(|_| ProtocolError::InvalidUtf8)
+}
+
+fn read_u16(buf: &[u8], index: usize) -> Result<u16, ProtocolError> {
+    let bytes = buf.get(index..index + 2).ok_or(ProtocolError::Truncated)?;
+    Ok(u16::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(buf: &[u8], index: usize) -> Result<u32, ProtocolError> {
+    let bytes = buf.get(index..index + 4).ok_or(ProtocolError::Truncated)?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(buf: &[u8], index: usize) -> Result<u64, ProtocolError> {
+    let bytes = buf.get(index..index + 8).ok_or(ProtocolError::Truncated)?;
+    Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_config(buf: &[u8], index: usize) -> Result<BoardConfig, ProtocolError> {
+    BoardConfig::new(
+        read_byte(buf, index)?,
+        read_byte(buf, index + 1)?,
+        read_byte(buf, index + 2)?,
+    )
+    .map_err(|_| ProtocolError::InvalidConfig)
+}
+
+fn read_color(buf: &[u8], index: usize) -> Result<Color, ProtocolError> {
+    Color::try_from(read_byte(buf, index)?)
+}
+
+/// Reads a count of lines starting at `index`, each one a length followed by (row, col) pairs.
+fn read_lines(buf: &[u8], mut index: usize) -> Result<Vec<Vec<(u8, u8)>>, ProtocolError> {
+    let count = read_byte(buf, index)?;
+    index += 1;
+    let mut lines = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let len = read_byte(buf, index)?;
+        index += 1;
+        let mut line = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            line.push((read_byte(buf, index)?, read_byte(buf, index + 1)?));
+            index += 2;
+        }
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+/// Reads a string of at most 255 bytes that starts with its length, returning it and the index
+/// just past it.
+fn read_short_string(buf: &[u8], index: usize) -> Result<(String, usize), ProtocolError> {
+    let end = index + 1 + read_byte(buf, index)? as usize;
+    let bytes = buf.get(index + 1..end).ok_or(ProtocolError::Truncated)?;
+    Ok((read_string(bytes)?, end))
+}
+
+/// Writes `text`, cut down to `max` bytes, after its length, for `read_short_string`.
+fn write_short_string(buf: &mut Vec<u8>, text: &str, max: usize) {
+    let text = truncate_str(text, max.min(255));
+    buf.push(text.len() as u8);
+    buf.extend(text.as_bytes());
+}
+
+/// Cuts `text` down to at most `max` bytes without splitting a character.
+fn truncate_str(text: &str, max: usize) -> &str {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
 pub enum ServerBoundPacket {
-    Init { name: String },
-    Move { col: u8 },
+    /// `bot` asks the server to seat a computer opponent instead of waiting for a second player.
+    /// `version` and `capabilities` describe the client, and are answered with a `Welcome`.
+    Init {
+        name: String,
+        bot: Option<Difficulty>,
+        version: u8,
+        capabilities: Capabilities,
+    },
+    Move {
+        col: u8,
+    },
     Forfeit,
+    /// Asks the server for a `ClientBoundPacket::State` of the current game.
+    RequestState,
+    /// Sent instead of `Init` by a client that lost its connection, to take back its seat in the
+    /// game that handed it `token`.
+    Resume {
+        #[cfg_attr(feature = "serde", serde(with = "token_string"))]
+        token: u64,
+    },
+    /// Joins the matchmaking queue. Clients older than version 4 are put in it by an `Init`
+    /// without a bot, and only need this after a `CancelQueue`.
+    Queue,
+    /// Leaves the matchmaking queue, or closes the room the player is waiting in, without
+    /// disconnecting.
+    CancelQueue,
+    /// Opens a private room, answered with a `RoomCreated` holding the code to join it with.
+    CreateRoom,
+    /// Joins the room someone else opened, starting a game with them.
+    JoinRoom {
+        code: String,
+    },
+    /// Asks for a `GameList` of the games that can be watched. Needs `Capabilities::SPECTATE`.
+    ListGames,
+    /// Leaves the lobby to watch the game with this id, starting with a `State` of it and then
+    /// every move and the result. Needs `Capabilities::SPECTATE`.
+    Watch {
+        id: u32,
+    },
+    /// Says something to the other player, and to spectators if the server lets them listen.
+    /// Needs `Capabilities::CHAT`.
+    Chat {
+        text: String,
+    },
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
 pub enum ClientBoundPacket {
+    /// `token` lets the player resume this game if their connection drops.
     GameStart {
         opponent: String,
         your_color: Color,
+        config: BoardConfig,
+        #[cfg_attr(feature = "serde", serde(with = "token_string"))]
+        token: u64,
     },
     Move {
         col: u8,
         color: Color,
     },
+    /// `col` and `color` are the move that ended the game. For a game that ended any other way
+    /// `col` is `None` and `color` is the player it ended for, such as the one who forfeited.
     GameResult {
         result: GameResult,
         col: Option<u8>,
         color: Color,
+        reason: EndReason,
+        /// The lines that won the game, as (row, col) pairs counted from the top left. Empty for
+        /// draws.
+        lines: Vec<Vec<(u8, u8)>>,
+    },
+    /// The whole game as the server sees it, so that a player who joined, resumed or lost track
+    /// can rebuild their board without replaying moves.
+    State {
+        red: String,
+        yellow: String,
+        config: BoardConfig,
+        /// Every cell, row by row from the top left like `Board::cell`.
+        cells: Vec<Cell>,
+        to_move: Color,
+        /// How many moves have been played so far.
+        move_number: u8,
+    },
+    /// Tells a player that the server refused their last packet, and why.
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+    /// Accepts an `Init`, with the version the server will speak and the capabilities both sides
+    /// share.
+    Welcome {
+        version: u8,
+        capabilities: Capabilities,
+    },
+    /// Where a player stands in the matchmaking queue, counting from 1 for the next to be
+    /// matched. Sent whenever it changes, and with 0 once they have left it.
+    QueuePosition {
+        position: u16,
+    },
+    /// Answers a `CreateRoom`. The game starts once someone joins with `code`.
+    RoomCreated {
+        code: String,
+    },
+    /// Answers a `ListGames` with every game being played.
+    GameList {
+        games: Vec<GameSummary>,
+    },
+    /// Something a player said, sent to everyone in the game including them.
+    Chat {
+        from: String,
+        text: String,
     },
 }
-#[derive(PartialEq, Debug)]
 
+/// A game being played, as listed for spectators.
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GameSummary {
+    /// What to `Watch` the game by.
+    pub id: u32,
+    pub red: String,
+    pub yellow: String,
+}
+
+/// Machine-readable reasons for a `ClientBoundPacket::Error`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ErrorCode {
+    /// The packet could not be decoded.
+    MalformedPacket,
+    /// The packet was valid but makes no sense at this point, such as an `Init` mid-game.
+    UnexpectedPacket,
+    NotYourTurn,
+    /// The column is off the board or already full.
+    IllegalMove,
+    GameOver,
+    /// A `Resume` named a token that no running game knows about.
+    UnknownSession,
+    /// The client speaks a protocol version the server does not.
+    VersionMismatch,
+    /// A `JoinRoom` named a code that no open room has.
+    UnknownRoom,
+    /// A `Watch` named a game that is over or never existed.
+    UnknownGame,
+    /// A `Chat` was empty, too long, or sent too soon after the ones before it.
+    ChatRejected,
+    /// The client's `Init` left out a capability the server cannot do without, such as
+    /// `VARIANTS` on a server whose board is not the standard one.
+    MissingCapability,
+}
+
+impl From<ErrorCode> for u8 {
+    fn from(code: ErrorCode) -> u8 {
+        match code {
+            ErrorCode::MalformedPacket => 0,
+            ErrorCode::UnexpectedPacket => 1,
+            ErrorCode::NotYourTurn => 2,
+            ErrorCode::IllegalMove => 3,
+            ErrorCode::GameOver => 4,
+            ErrorCode::UnknownSession => 5,
+            ErrorCode::VersionMismatch => 6,
+            ErrorCode::UnknownRoom => 7,
+            ErrorCode::UnknownGame => 8,
+            ErrorCode::ChatRejected => 9,
+            ErrorCode::MissingCapability => 10,
+        }
+    }
+}
+
+impl TryFrom<u8> for ErrorCode {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ErrorCode::MalformedPacket),
+            1 => Ok(ErrorCode::UnexpectedPacket),
+            2 => Ok(ErrorCode::NotYourTurn),
+            3 => Ok(ErrorCode::IllegalMove),
+            4 => Ok(ErrorCode::GameOver),
+            5 => Ok(ErrorCode::UnknownSession),
+            6 => Ok(ErrorCode::VersionMismatch),
+            7 => Ok(ErrorCode::UnknownRoom),
+            8 => Ok(ErrorCode::UnknownGame),
+            9 => Ok(ErrorCode::ChatRejected),
+            10 => Ok(ErrorCode::MissingCapability),
+            value => Err(ProtocolError::InvalidEnum {
+                field: "error code",
+                value,
+            }),
+        }
+    }
+}
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Color {
     Red,
     Yellow,
 }
-#[derive(PartialEq, Debug, Clone)]
 
+impl Color {
+    pub fn opponent(self) -> Color {
+        match self {
+            Color::Red => Color::Yellow,
+            Color::Yellow => Color::Red,
+        }
+    }
+
+    /// Where `self` goes in an array with an entry for each color, red first. This is its wire
+    /// encoding, so per-player arrays can be indexed straight from a packet.
+    pub fn index(self) -> usize {
+        u8::from(self) as usize
+    }
+}
+
+/// The single place colors are mapped to their wire encoding.
+impl From<Color> for u8 {
+    fn from(color: Color) -> u8 {
+        match color {
+            Color::Red => 0,
+            Color::Yellow => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for Color {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Color::Red),
+            1 => Ok(Color::Yellow),
+            value => Err(ProtocolError::InvalidEnum {
+                field: "color",
+                value,
+            }),
+        }
+    }
+}
+
+/// What occupies a single square of a `Board`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Cell {
+    Empty,
+    Piece(Color),
+}
+
+impl From<Cell> for u8 {
+    fn from(cell: Cell) -> u8 {
+        match cell {
+            Cell::Empty => 0,
+            Cell::Piece(color) => u8::from(color) + 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for Cell {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Cell::Empty),
+            value => Color::try_from(value - 1).map(Cell::Piece).map_err(|_| {
+                ProtocolError::InvalidEnum {
+                    field: "cell",
+                    value,
+                }
+            }),
+        }
+    }
+}
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum GameResult {
     InProgress,
     RedWin,
     YellowWin,
     Draw,
 }
+
+/// How a game came to an end.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum EndReason {
+    /// Someone connected four or the board filled up.
+    Completed,
+    /// A player resigned, or sent too many bad packets and was made to.
+    Forfeit,
+    /// A player's connection was lost.
+    Disconnected,
+}
+
+impl From<EndReason> for u8 {
+    fn from(reason: EndReason) -> u8 {
+        match reason {
+            EndReason::Completed => 0,
+            EndReason::Forfeit => 1,
+            EndReason::Disconnected => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for EndReason {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(EndReason::Completed),
+            1 => Ok(EndReason::Forfeit),
+            2 => Ok(EndReason::Disconnected),
+            value => Err(ProtocolError::InvalidEnum {
+                field: "end reason",
+                value,
+            }),
+        }
+    }
+}
+
 impl Serialize for ServerBoundPacket {
     fn serialize(&self) -> Vec<u8> {
         match self {
-            ServerBoundPacket::Init { name } => {
-                let mut buf = Vec::new();
-                buf.push(0);
-                if name.len() > 32 {
-                    buf.extend(&name.as_bytes()[..32]);
-                } else {
-                    buf.extend(name.as_bytes());
-                }
+            ServerBoundPacket::Init {
+                name,
+                bot,
+                version,
+                capabilities,
+            } => {
+                let mut buf = vec![0];
+                buf.extend(PROTOCOL_MAGIC);
+                buf.extend([*version, capabilities.bits()]);
+                buf.push(bot.map_or(0, Difficulty::to_byte));
+                buf.extend(truncate_str(name, 32).as_bytes());
+                buf
+            }
+            ServerBoundPacket::Move { col } => vec![1, *col],
+            ServerBoundPacket::Forfeit => vec![2],
+            ServerBoundPacket::RequestState => vec![4],
+            ServerBoundPacket::Resume { token } => {
+                let mut buf = vec![3];
+                buf.extend(token.to_be_bytes());
+                buf
+            }
+            ServerBoundPacket::Queue => vec![5],
+            ServerBoundPacket::CancelQueue => vec![6],
+            ServerBoundPacket::CreateRoom => vec![7],
+            ServerBoundPacket::JoinRoom { code } => {
+                let mut buf = vec![8];
+                buf.extend(truncate_str(code, 32).as_bytes());
                 buf
             }
-            ServerBoundPacket::Move { col } => {
-                let mut buf = Vec::new();
-                buf.push(1);
-                buf.push(*col);
+            ServerBoundPacket::ListGames => vec![9],
+            ServerBoundPacket::Watch { id } => {
+                let mut buf = vec![10];
+                buf.extend(id.to_be_bytes());
                 buf
             }
-            ServerBoundPacket::Forfeit => {
-                let mut buf = Vec::new();
-                buf.push(2);
+            ServerBoundPacket::Chat { text } => {
+                let mut buf = vec![11];
+                buf.extend(truncate_str(text, MAX_CHAT_LEN).as_bytes());
                 buf
             }
         }
@@ -92,14 +617,49 @@ impl Serialize for ServerBoundPacket {
 }
 
 impl Deserialize for ServerBoundPacket {
-    fn deserialize(buf: &[u8]) -> Self {
-        match buf[0] {
-            0 => ServerBoundPacket::Init {
-                name: String::from_utf8_lossy(&buf[1..]).to_string(),
-            },
-            1 => ServerBoundPacket::Move { col: buf[1] },
-            2 => ServerBoundPacket::Forfeit,
-            _ => panic!("Invalid packet type"),
+    fn deserialize(buf: &[u8]) -> Result<Self, ProtocolError> {
+        match read_byte(buf, 0)? {
+            0 => {
+                // checked before anything else, since the rest of the layout depends on them
+                if !buf[1..].starts_with(&PROTOCOL_MAGIC) {
+                    return Err(ProtocolError::BadMagic);
+                }
+                let version = read_byte(buf, 5)?;
+                if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+                    return Err(ProtocolError::UnsupportedVersion(version));
+                }
+                Ok(ServerBoundPacket::Init {
+                    version,
+                    capabilities: Capabilities::from_bits(read_byte(buf, 6)?),
+                    bot: match read_byte(buf, 7)? {
+                        0 => None,
+                        value => Some(Difficulty::from_byte(value)?),
+                    },
+                    name: read_string(&buf[8..])?,
+                })
+            }
+            1 => Ok(ServerBoundPacket::Move {
+                col: read_byte(buf, 1)?,
+            }),
+            2 => Ok(ServerBoundPacket::Forfeit),
+            3 => Ok(ServerBoundPacket::Resume {
+                token: read_u64(buf, 1)?,
+            }),
+            4 => Ok(ServerBoundPacket::RequestState),
+            5 => Ok(ServerBoundPacket::Queue),
+            6 => Ok(ServerBoundPacket::CancelQueue),
+            7 => Ok(ServerBoundPacket::CreateRoom),
+            8 => Ok(ServerBoundPacket::JoinRoom {
+                code: read_string(&buf[1..])?,
+            }),
+            9 => Ok(ServerBoundPacket::ListGames),
+            10 => Ok(ServerBoundPacket::Watch {
+                id: read_u32(buf, 1)?,
+            }),
+            11 => Ok(ServerBoundPacket::Chat {
+                text: read_string(&buf[1..])?,
+            }),
+            opcode => Err(ProtocolError::UnknownOpcode(opcode)),
         }
     }
 }
@@ -110,30 +670,29 @@ impl Serialize for ClientBoundPacket {
             ClientBoundPacket::GameStart {
                 opponent,
                 your_color,
+                config,
+                token,
             } => {
-                let mut buf = Vec::new();
-                buf.push(0);
-                buf.extend(opponent.as_bytes());
-
-                buf.push(match your_color {
-                    Color::Red => 0,
-                    Color::Yellow => 1,
-                });
+                let mut buf = vec![0];
+                buf.push(u8::from(*your_color));
+                buf.extend([config.rows(), config.cols(), config.connect()]);
+                buf.extend(token.to_be_bytes());
+                buf.extend(truncate_str(opponent, 32).as_bytes());
                 buf
             }
             ClientBoundPacket::Move { col, color } => {
-                let mut buf = Vec::new();
-                buf.push(1);
-                buf.push(*col);
-                buf.push(match color {
-                    Color::Red => 0,
-                    Color::Yellow => 1,
-                });
+                let mut buf = vec![1, *col];
+                buf.push(u8::from(*color));
                 buf
             }
-            ClientBoundPacket::GameResult { result, col, color } => {
-                let mut buf = Vec::new();
-                buf.push(2);
+            ClientBoundPacket::GameResult {
+                result,
+                col,
+                color,
+                reason,
+                lines,
+            } => {
+                let mut buf = vec![2];
                 buf.push(match result {
                     GameResult::RedWin => 0,
                     GameResult::YellowWin => 1,
@@ -147,10 +706,70 @@ impl Serialize for ClientBoundPacket {
                 } else {
                     buf.push(255);
                 }
-                buf.push(match color {
-                    Color::Red => 0,
-                    Color::Yellow => 1,
-                });
+                buf.push(u8::from(*color));
+                buf.push(u8::from(*reason));
+                buf.push(lines.len() as u8);
+                for line in lines {
+                    buf.push(line.len() as u8);
+                    for &(row, col) in line {
+                        buf.extend([row, col]);
+                    }
+                }
+                buf
+            }
+            ClientBoundPacket::State {
+                red,
+                yellow,
+                config,
+                cells,
+                to_move,
+                move_number,
+            } => {
+                let mut buf = vec![4];
+                buf.extend([config.rows(), config.cols(), config.connect()]);
+                buf.push(u8::from(*to_move));
+                buf.push(*move_number);
+                buf.extend(cells.iter().map(|&cell| u8::from(cell)));
+                let red = truncate_str(red, 32);
+                buf.push(red.len() as u8);
+                buf.extend(red.as_bytes());
+                buf.extend(truncate_str(yellow, 32).as_bytes());
+                buf
+            }
+            ClientBoundPacket::Error { code, message } => {
+                let mut buf = vec![3, u8::from(*code)];
+                buf.extend(truncate_str(message, 200).as_bytes());
+                buf
+            }
+            ClientBoundPacket::Welcome {
+                version,
+                capabilities,
+            } => vec![5, *version, capabilities.bits()],
+            ClientBoundPacket::QueuePosition { position } => {
+                let mut buf = vec![6];
+                buf.extend(position.to_be_bytes());
+                buf
+            }
+            ClientBoundPacket::RoomCreated { code } => {
+                let mut buf = vec![7];
+                buf.extend(truncate_str(code, 32).as_bytes());
+                buf
+            }
+            ClientBoundPacket::GameList { games } => {
+                let mut buf = vec![8];
+                let count = games.len().min(u16::MAX as usize);
+                buf.extend((count as u16).to_be_bytes());
+                for game in &games[..count] {
+                    buf.extend(game.id.to_be_bytes());
+                    write_short_string(&mut buf, &game.red, 32);
+                    write_short_string(&mut buf, &game.yellow, 32);
+                }
+                buf
+            }
+            ClientBoundPacket::Chat { from, text } => {
+                let mut buf = vec![9];
+                write_short_string(&mut buf, from, 32);
+                buf.extend(truncate_str(text, MAX_CHAT_LEN).as_bytes());
                 buf
             }
         }
@@ -158,42 +777,95 @@ impl Serialize for ClientBoundPacket {
 }
 
 impl Deserialize for ClientBoundPacket {
-    fn deserialize(buf: &[u8]) -> Self {
-        match buf[0] {
-            0 => ClientBoundPacket::GameStart {
-                opponent: String::from_utf8_lossy(&buf[1..buf.len() - 1]).to_string(),
-                your_color: match buf[buf.len() - 1] {
-                    0 => Color::Red,
-                    1 => Color::Yellow,
-                    _ => panic!("Invalid color"),
-                },
-            },
-            1 => ClientBoundPacket::Move {
-                col: buf[1],
-                color: match buf[2] {
-                    0 => Color::Red,
-                    1 => Color::Yellow,
-                    _ => panic!("Invalid color"),
-                },
-            },
-            2 => ClientBoundPacket::GameResult {
-                result: match buf[1] {
+    fn deserialize(buf: &[u8]) -> Result<Self, ProtocolError> {
+        match read_byte(buf, 0)? {
+            0 => Ok(ClientBoundPacket::GameStart {
+                your_color: read_color(buf, 1)?,
+                config: read_config(buf, 2)?,
+                token: read_u64(buf, 5)?,
+                opponent: read_string(&buf[13..])?,
+            }),
+            1 => Ok(ClientBoundPacket::Move {
+                col: read_byte(buf, 1)?,
+                color: read_color(buf, 2)?,
+            }),
+            2 => Ok(ClientBoundPacket::GameResult {
+                result: match read_byte(buf, 1)? {
                     0 => GameResult::RedWin,
                     1 => GameResult::YellowWin,
                     2 => GameResult::Draw,
-                    _ => panic!("Invalid game result"),
-                },
-                col: match buf[2] {
-                    0..=6 => Some(buf[2]),
-                    _ => None,
+                    value => {
+                        return Err(ProtocolError::InvalidEnum {
+                            field: "game result",
+                            value,
+                        })
+                    }
                 },
-                color: match buf[3] {
-                    0 => Color::Red,
-                    1 => Color::Yellow,
-                    _ => panic!("Invalid color"),
+                col: match read_byte(buf, 2)? {
+                    255 => None,
+                    col => Some(col),
                 },
-            },
-            _ => panic!("Invalid packet type"),
+                color: read_color(buf, 3)?,
+                reason: EndReason::try_from(read_byte(buf, 4)?)?,
+                lines: read_lines(buf, 5)?,
+            }),
+            3 => Ok(ClientBoundPacket::Error {
+                code: ErrorCode::try_from(read_byte(buf, 1)?)?,
+                message: read_string(&buf[2..])?,
+            }),
+            4 => {
+                let config = read_config(buf, 1)?;
+                let end = 6 + config.rows() as usize * config.cols() as usize;
+                let cells = buf
+                    .get(6..end)
+                    .ok_or(ProtocolError::Truncated)?
+                    .iter()
+                    .map(|&cell| Cell::try_from(cell))
+                    .collect::<Result<_, _>>()?;
+                let len = read_byte(buf, end)? as usize;
+                let red = buf
+                    .get(end + 1..end + 1 + len)
+                    .ok_or(ProtocolError::Truncated)?;
+                Ok(ClientBoundPacket::State {
+                    red: read_string(red)?,
+                    yellow: read_string(&buf[end + 1 + len..])?,
+                    config,
+                    cells,
+                    to_move: read_color(buf, 4)?,
+                    move_number: read_byte(buf, 5)?,
+                })
+            }
+            5 => Ok(ClientBoundPacket::Welcome {
+                version: read_byte(buf, 1)?,
+                capabilities: Capabilities::from_bits(read_byte(buf, 2)?),
+            }),
+            6 => Ok(ClientBoundPacket::QueuePosition {
+                position: read_u16(buf, 1)?,
+            }),
+            7 => Ok(ClientBoundPacket::RoomCreated {
+                code: read_string(&buf[1..])?,
+            }),
+            8 => {
+                let count = read_u16(buf, 1)?;
+                let mut index = 3;
+                let mut games = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let id = read_u32(buf, index)?;
+                    let (red, end) = read_short_string(buf, index + 4)?;
+                    let (yellow, end) = read_short_string(buf, end)?;
+                    index = end;
+                    games.push(GameSummary { id, red, yellow });
+                }
+                Ok(ClientBoundPacket::GameList { games })
+            }
+            9 => {
+                let (from, end) = read_short_string(buf, 1)?;
+                Ok(ClientBoundPacket::Chat {
+                    from,
+                    text: read_string(&buf[end..])?,
+                })
+            }
+            opcode => Err(ProtocolError::UnknownOpcode(opcode)),
         }
     }
 }
@@ -203,14 +875,60 @@ fn test_serialize_deserialize() {
     let serverbound_packets = vec![
         ServerBoundPacket::Init {
             name: "Blechdavier".to_string(),
+            bot: None,
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::NONE,
+        },
+        ServerBoundPacket::Init {
+            name: "Blechdavier".to_string(),
+            bot: Some(Difficulty::Hard),
+            version: MIN_PROTOCOL_VERSION,
+            capabilities: Capabilities::CHAT | Capabilities::VARIANTS,
         },
         ServerBoundPacket::Move { col: 3 },
         ServerBoundPacket::Forfeit,
+        ServerBoundPacket::RequestState,
+        ServerBoundPacket::Resume { token: 0xfeed_f00d },
+        ServerBoundPacket::Queue,
+        ServerBoundPacket::CancelQueue,
+        ServerBoundPacket::CreateRoom,
+        ServerBoundPacket::JoinRoom {
+            code: "K7QX2M".to_string(),
+        },
+        ServerBoundPacket::ListGames,
+        ServerBoundPacket::Watch { id: 70000 },
+        ServerBoundPacket::Chat {
+            text: "good game!".to_string(),
+        },
     ];
     let clientbound_packets = vec![
         ClientBoundPacket::GameStart {
             opponent: "Blechdavier".to_string(),
             your_color: Color::Red,
+            config: BoardConfig::default(),
+            token: 0x0123_4567_89ab_cdef,
+        },
+        ClientBoundPacket::GameStart {
+            opponent: String::new(),
+            your_color: Color::Yellow,
+            config: BoardConfig::new(9, 9, 5).unwrap(),
+            token: 0,
+        },
+        ClientBoundPacket::State {
+            red: "Blechdavier".to_string(),
+            yellow: "Bot (hard)".to_string(),
+            config: BoardConfig::default(),
+            cells: Board::from_moves("4453").unwrap().cells(),
+            to_move: Color::Red,
+            move_number: 4,
+        },
+        ClientBoundPacket::State {
+            red: String::new(),
+            yellow: String::new(),
+            config: BoardConfig::new(2, 3, 2).unwrap(),
+            cells: vec![Cell::Empty; 6],
+            to_move: Color::Red,
+            move_number: 0,
         },
         ClientBoundPacket::Move {
             col: 3,
@@ -220,149 +938,1169 @@ fn test_serialize_deserialize() {
             result: GameResult::RedWin,
             col: Some(3),
             color: Color::Red,
+            reason: EndReason::Completed,
+            lines: vec![
+                vec![(2, 5), (3, 5), (4, 5), (5, 5)],
+                vec![(5, 2), (4, 3), (3, 4), (2, 5)],
+            ],
         },
         ClientBoundPacket::GameResult {
             result: GameResult::Draw,
             col: None,
             color: Color::Yellow,
+            reason: EndReason::Completed,
+            lines: Vec::new(),
+        },
+        ClientBoundPacket::GameResult {
+            result: GameResult::YellowWin,
+            col: None,
+            color: Color::Red,
+            reason: EndReason::Forfeit,
+            lines: Vec::new(),
+        },
+        ClientBoundPacket::GameResult {
+            result: GameResult::RedWin,
+            col: None,
+            color: Color::Yellow,
+            reason: EndReason::Disconnected,
+            lines: Vec::new(),
+        },
+        ClientBoundPacket::Error {
+            code: ErrorCode::NotYourTurn,
+            message: "it is not your turn".to_string(),
+        },
+        ClientBoundPacket::Error {
+            code: ErrorCode::MissingCapability,
+            message: String::new(),
+        },
+        ClientBoundPacket::Welcome {
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::VARIANTS,
+        },
+        ClientBoundPacket::QueuePosition { position: 0 },
+        ClientBoundPacket::QueuePosition { position: 300 },
+        ClientBoundPacket::RoomCreated {
+            code: "K7QX2M".to_string(),
+        },
+        ClientBoundPacket::GameList { games: Vec::new() },
+        ClientBoundPacket::GameList {
+            games: vec![
+                GameSummary {
+                    id: 1,
+                    red: "Blechdavier".to_string(),
+                    yellow: "Bot (hard)".to_string(),
+                },
+                GameSummary {
+                    id: 70000,
+                    red: "é".to_string(),
+                    yellow: String::new(),
+                },
+            ],
+        },
+        ClientBoundPacket::Chat {
+            from: "Blechdavier".to_string(),
+            text: "good game!".to_string(),
         },
     ];
     for packet in serverbound_packets {
-        assert_eq!(packet, ServerBoundPacket::deserialize(&packet.serialize()));
+        let buf = packet.serialize();
+        assert_eq!(Ok(packet), ServerBoundPacket::deserialize(&buf));
     }
     for packet in clientbound_packets {
-        assert_eq!(packet, ClientBoundPacket::deserialize(&packet.serialize()));
+        let buf = packet.serialize();
+        assert_eq!(Ok(packet), ClientBoundPacket::deserialize(&buf));
+    }
+}
+
+#[test]
+fn test_capabilities() {
+    let client = Capabilities::CHAT | Capabilities::VARIANTS;
+    let server = Capabilities::VARIANTS | Capabilities::SPECTATE;
+    assert_eq!(client.intersection(server), Capabilities::VARIANTS);
+    assert!(client.contains(Capabilities::CHAT));
+    assert!(!client.contains(Capabilities::CHAT | Capabilities::SPECTATE));
+    assert!(client.contains(Capabilities::NONE));
+    assert_eq!(client.union(server), client | server);
+    assert_eq!(client.to_string(), "chat, variants");
+    assert_eq!(Capabilities::NONE.to_string(), "none");
+    // bits from a newer peer survive the trip, but are never shared with an older one
+    let newer = Capabilities::from_bits(0b1000_0001);
+    assert_eq!(newer.bits(), 0b1000_0001);
+    assert_eq!(newer.intersection(client), Capabilities::CHAT);
+}
+
+#[test]
+fn test_deserialize_malformed() {
+    assert_eq!(
+        ServerBoundPacket::deserialize(&[]),
+        Err(ProtocolError::Truncated)
+    );
+    assert_eq!(
+        ServerBoundPacket::deserialize(&[1]),
+        Err(ProtocolError::Truncated)
+    );
+    assert_eq!(
+        ServerBoundPacket::deserialize(&[200]),
+        Err(ProtocolError::UnknownOpcode(200))
+    );
+    assert_eq!(
+        ServerBoundPacket::deserialize(&[0, b'C', b'4', b'M', b'P', 2, 0, 0, 0xff, 0xfe]),
+        Err(ProtocolError::InvalidUtf8)
+    );
+    assert_eq!(
+        ServerBoundPacket::deserialize(&[0, b'C', b'4', b'M', b'P', 2, 0, 9]),
+        Err(ProtocolError::InvalidEnum {
+            field: "difficulty",
+            value: 9
+        })
+    );
+    // an init from before the protocol was versioned: no bot, named "Bob"
+    assert_eq!(
+        ServerBoundPacket::deserialize(&[0, 0, b'B', b'o', b'b']),
+        Err(ProtocolError::BadMagic)
+    );
+    assert_eq!(
+        ServerBoundPacket::deserialize(&[0, b'C', b'4', b'M', b'P', 200, 0xff]),
+        Err(ProtocolError::UnsupportedVersion(200))
+    );
+    assert_eq!(
+        ServerBoundPacket::deserialize(&[0, b'C', b'4', b'M', b'P', 1, 0, 0]),
+        Err(ProtocolError::UnsupportedVersion(1))
+    );
+    assert_eq!(
+        ServerBoundPacket::deserialize(&[0, b'C', b'4', b'M', b'P', 2]),
+        Err(ProtocolError::Truncated)
+    );
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[0]),
+        Err(ProtocolError::Truncated)
+    );
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[0, 0, 6, 0, 4]),
+        Err(ProtocolError::InvalidConfig)
+    );
+    assert_eq!(
+        ServerBoundPacket::deserialize(&[3, 1, 2, 3]),
+        Err(ProtocolError::Truncated)
+    );
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[4, 2, 2, 2, 0, 1, 1, 0, 0]),
+        Err(ProtocolError::Truncated)
+    );
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[4, 2, 2, 2, 0, 1, 1, 0, 0, 9, 0]),
+        Err(ProtocolError::InvalidEnum {
+            field: "cell",
+            value: 9
+        })
+    );
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[4, 2, 2, 2, 0, 1, 1, 0, 0, 0, 5, b'a']),
+        Err(ProtocolError::Truncated)
+    );
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[1, 3, 7]),
+        Err(ProtocolError::InvalidEnum {
+            field: "color",
+            value: 7
+        })
+    );
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[2, 5, 3, 0]),
+        Err(ProtocolError::InvalidEnum {
+            field: "game result",
+            value: 5
+        })
+    );
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[2, 0, 3, 0, 0, 1, 4, 5, 0]),
+        Err(ProtocolError::Truncated)
+    );
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[2, 0, 3, 0, 7, 0]),
+        Err(ProtocolError::InvalidEnum {
+            field: "end reason",
+            value: 7
+        })
+    );
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[3, 200]),
+        Err(ProtocolError::InvalidEnum {
+            field: "error code",
+            value: 200
+        })
+    );
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[6, 1]),
+        Err(ProtocolError::Truncated)
+    );
+    // a listed name longer than what is left of the packet
+    assert_eq!(
+        ClientBoundPacket::deserialize(&[8, 0, 1, 0, 0, 0, 1, 5, b'B', b'o', b'b']),
+        Err(ProtocolError::Truncated)
+    );
+    // names are cut at 32 bytes without splitting a multi-byte character
+    let packet = ServerBoundPacket::Init {
+        name: "é".repeat(20),
+        bot: None,
+        version: PROTOCOL_VERSION,
+        capabilities: Capabilities::NONE,
+    };
+    assert_eq!(
+        ServerBoundPacket::deserialize(&packet.serialize()),
+        Ok(ServerBoundPacket::Init {
+            name: "é".repeat(16),
+            bot: None,
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::NONE,
+        })
+    );
+    // and chat at `MAX_CHAT_LEN`
+    let packet = ClientBoundPacket::Chat {
+        from: "Bob".to_string(),
+        text: "a".repeat(MAX_CHAT_LEN + 50),
+    };
+    assert_eq!(
+        ClientBoundPacket::deserialize(&packet.serialize()),
+        Ok(ClientBoundPacket::Chat {
+            from: "Bob".to_string(),
+            text: "a".repeat(MAX_CHAT_LEN),
+        })
+    );
+}
+
+/// The shape of a board and how many pieces in a row win.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "ConfigRecord"))]
+pub struct BoardConfig {
+    rows: u8,
+    cols: u8,
+    connect: u8,
+}
+
+/// Reasons a `BoardConfig` was rejected.
+#[derive(PartialEq, Debug)]
+pub enum ConfigError {
+    /// The board has no rows or no columns.
+    Empty,
+    /// The connect length is below 2 or does not fit on the board.
+    InvalidConnect,
+    /// The board needs more than 128 bits to store, counting one guard row.
+    TooLarge,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Empty => write!(f, "the board needs at least one row and column"),
+            ConfigError::InvalidConnect => {
+                write!(
+                    f,
+                    "the connect length must be at least 2 and fit on the board"
+                )
+            }
+            ConfigError::TooLarge => write!(f, "(rows + 1) * cols must be at most 128"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl BoardConfig {
+    /// The classic 6 row, 7 column, connect 4 board.
+    pub const STANDARD: BoardConfig = BoardConfig {
+        rows: 6,
+        cols: 7,
+        connect: 4,
+    };
+
+    pub fn new(rows: u8, cols: u8, connect: u8) -> Result<Self, ConfigError> {
+        if rows == 0 || cols == 0 {
+            return Err(ConfigError::Empty);
+        }
+        if connect < 2 || connect > rows.max(cols) {
+            return Err(ConfigError::InvalidConnect);
+        }
+        if (rows as usize + 1) * cols as usize > 128 {
+            return Err(ConfigError::TooLarge);
+        }
+        Ok(BoardConfig {
+            rows,
+            cols,
+            connect,
+        })
+    }
+
+    pub fn rows(&self) -> u8 {
+        self.rows
+    }
+
+    pub fn cols(&self) -> u8 {
+        self.cols
+    }
+
+    pub fn connect(&self) -> u8 {
+        self.connect
+    }
+
+    /// Each column takes `rows + 1` bits so that an empty guard bit separates it from the next one.
+    fn col_stride(&self) -> usize {
+        self.rows as usize + 1
+    }
+}
+
+impl Default for BoardConfig {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+/// A `BoardConfig` as it is written out, checked by `BoardConfig::new` on the way back in.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct ConfigRecord {
+    rows: u8,
+    cols: u8,
+    connect: u8,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ConfigRecord> for BoardConfig {
+    type Error = ConfigError;
+
+    fn try_from(record: ConfigRecord) -> Result<Self, Self::Error> {
+        BoardConfig::new(record.rows, record.cols, record.connect)
+    }
+}
+
+/// Reasons a move cannot be played.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum MoveError {
+    /// The column does not exist on this board.
+    OutOfRange,
+    ColumnFull,
+    /// Someone has already won.
+    GameOver,
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoveError::OutOfRange => write!(f, "column is out of range"),
+            MoveError::ColumnFull => write!(f, "column is full"),
+            MoveError::GameOver => write!(f, "game is already over"),
+        }
+    }
+}
+
+impl Error for MoveError {}
+
+/// Reasons a move sequence could not be turned into a board. `index` counts characters from 0.
+#[derive(PartialEq, Debug)]
+pub enum NotationError {
+    InvalidCharacter {
+        index: usize,
+        found: char,
+    },
+    IllegalMove {
+        index: usize,
+        column: char,
+        error: MoveError,
+    },
+}
+
+impl Display for NotationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotationError::InvalidCharacter { index, found } => {
+                write!(f, "move {} ({:?}) is not a column", index + 1, found)
+            }
+            NotationError::IllegalMove {
+                index,
+                column,
+                error,
+            } => write!(f, "move {} (column {}): {}", index + 1, column, error),
+        }
+    }
+}
+
+impl Error for NotationError {}
+
+/// The four directions a line can run in, as (column, row) steps.
+const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+
+/// Reasons a grid of cells could not be turned into a board.
+#[derive(PartialEq, Debug)]
+pub enum GridError {
+    /// The number of cells does not match the board's rows and columns.
+    WrongSize,
+    /// A piece sits above an empty square.
+    FloatingPiece,
+    /// Red moves first, so red must have as many pieces as yellow or one more.
+    PieceCount,
+    /// Both players have a line.
+    TwoWinners,
+}
+
+impl Display for GridError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GridError::WrongSize => write!(f, "the grid does not match the board size"),
+            GridError::FloatingPiece => write!(f, "a piece is floating above an empty square"),
+            GridError::PieceCount => write!(f, "the number of red and yellow pieces is impossible"),
+            GridError::TwoWinners => write!(f, "both players have connected"),
+        }
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Board([[i32; 7]; 6]);
+impl Error for GridError {}
+
+/// A board stored as one bitboard per player.
+///
+/// Bit `col * (rows + 1) + row` is set when that player has a piece in `col`, counting rows from
+/// the bottom. The winner is worked out incrementally from the lines through each placed piece,
+/// so `score` never has to rescan the board.
+///
+/// The board also remembers the order moves were played in, so they can be undone and redone.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(into = "BoardRecord", try_from = "BoardRecord")
+)]
+pub struct Board {
+    config: BoardConfig,
+    red: u128,
+    yellow: u128,
+    heights: Vec<u8>,
+    /// The first piece to connect, and how many moves had been played when it did. Boards built
+    /// from a grid have no history and record 0.
+    winner: Option<(Color, usize)>,
+    moves: Vec<u8>,
+    /// Undone moves with their pieces, the most recently undone last.
+    undone: Vec<(u8, Color)>,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A `Board` as it is written out: the moves that led to it, so that it can be rebuilt with its
+/// history, and the grid for anyone who only wants to draw it. Boards built from a grid have no
+/// moves, and are rebuilt from the grid instead.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BoardRecord {
+    config: BoardConfig,
+    #[serde(default)]
+    moves: Vec<u8>,
+    #[serde(default)]
+    cells: Option<Vec<Cell>>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Board> for BoardRecord {
+    fn from(board: Board) -> Self {
+        BoardRecord {
+            config: board.config(),
+            moves: board.moves().to_vec(),
+            cells: Some(board.cells()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<BoardRecord> for Board {
+    type Error = String;
+
+    fn try_from(record: BoardRecord) -> Result<Self, Self::Error> {
+        if let (true, Some(cells)) = (record.moves.is_empty(), &record.cells) {
+            return Board::from_cells(record.config, cells).map_err(|e| e.to_string());
+        }
+        let mut board = Board::with_config(record.config);
+        for (index, &col) in record.moves.iter().enumerate() {
+            board
+                .play_move(col, board.to_move())
+                .map_err(|e| format!("move {} in column {}: {}", index + 1, col, e))?;
+        }
+        match record.cells {
+            Some(cells) if cells != board.cells() => {
+                Err("the cells do not match the moves".to_string())
+            }
+            _ => Ok(board),
+        }
+    }
+}
 
 impl Board {
     pub fn new() -> Self {
-        Board([[0; 7]; 6])
+        Self::with_config(BoardConfig::STANDARD)
+    }
+
+    pub fn with_config(config: BoardConfig) -> Self {
+        Board {
+            config,
+            red: 0,
+            yellow: 0,
+            heights: vec![0; config.cols as usize],
+            winner: None,
+            moves: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> BoardConfig {
+        self.config
     }
+
     pub fn score(&self) -> GameResult {
-        let board = self.0;
-        // check for horizontal wins
-        for row in 0..6 {
-            for col in 0..4 {
-                if board[row][col] != 0
-                    && board[row][col] == board[row][col + 1]
-                    && board[row][col] == board[row][col + 2]
-                    && board[row][col] == board[row][col + 3]
-                {
-                    return match board[row][col] {
-                        1 => GameResult::RedWin,
-                        2 => GameResult::YellowWin,
-                        _ => panic!("Invalid board state"),
-                    };
-                }
-            }
+        match self.winner.map(|(color, _)| color) {
+            Some(Color::Red) => GameResult::RedWin,
+            Some(Color::Yellow) => GameResult::YellowWin,
+            None if self.heights.iter().all(|&h| h == self.config.rows) => GameResult::Draw,
+            None => GameResult::InProgress,
         }
-        // check for vertical wins
-        for row in 0..3 {
-            for col in 0..7 {
-                if board[row][col] != 0
-                    && board[row][col] == board[row + 1][col]
-                    && board[row][col] == board[row + 2][col]
-                    && board[row][col] == board[row + 3][col]
-                {
-                    return match board[row][col] {
-                        1 => GameResult::RedWin,
-                        2 => GameResult::YellowWin,
-                        _ => panic!("Invalid board state"),
-                    };
+    }
+
+    /// Returns every line of the winner's pieces, or nothing if nobody has won. The winning move
+    /// can finish several lines at once, and a line can be longer than `connect`.
+    ///
+    /// Cells are (row, col) pairs counted like `cell`, ordered from left to right and then from
+    /// top to bottom.
+    pub fn winning_lines(&self) -> Vec<Vec<(u8, u8)>> {
+        let pieces = match self.winner {
+            Some((Color::Red, _)) => self.red,
+            Some((Color::Yellow, _)) => self.yellow,
+            None => return Vec::new(),
+        };
+        let (rows, cols) = (self.config.rows as isize, self.config.cols as isize);
+        let mut lines = Vec::new();
+        for (dc, dr) in DIRECTIONS {
+            for col in 0..cols {
+                for row in 0..rows {
+                    // only count each run from its first piece
+                    if !self.occupied(pieces, col, row) || self.occupied(pieces, col - dc, row - dr)
+                    {
+                        continue;
+                    }
+                    let len = 1 + self.run(pieces, col as usize, row as usize, dc, dr) as isize;
+                    if len < self.config.connect as isize {
+                        continue;
+                    }
+                    let mut line: Vec<(u8, u8)> = (0..len)
+                        .map(|i| ((rows - 1 - (row + dr * i)) as u8, (col + dc * i) as u8))
+                        .collect();
+                    line.sort_by_key(|&(row, col)| (col, row));
+                    lines.push(line);
                 }
             }
         }
-        // check for diagonal wins
-        for row in 0..3 {
-            for col in 0..4 {
-                if board[row][col] != 0
-                    && board[row][col] == board[row + 1][col + 1]
-                    && board[row][col] == board[row + 2][col + 2]
-                    && board[row][col] == board[row + 3][col + 3]
-                {
-                    return match board[row][col] {
-                        1 => GameResult::RedWin,
-                        2 => GameResult::YellowWin,
-                        _ => panic!("Invalid board state"),
-                    };
-                }
-            }
+        lines
+    }
+
+    /// Renders the board like `Display`, but draws the pieces in `cells` as squares so that a
+    /// winning line stands out. Cells are (row, col) pairs counted like `cell`.
+    pub fn highlight<'a>(&'a self, cells: &'a [(u8, u8)]) -> Highlighted<'a> {
+        Highlighted { board: self, cells }
+    }
+
+    /// Drops a `color` piece into `col`. This forgets any moves that could have been redone.
+    pub fn play_move(&mut self, col: u8, color: Color) -> Result<(), MoveError> {
+        self.place(col, color)?;
+        self.undone.clear();
+        Ok(())
+    }
+
+    /// Takes back the last move and returns its column.
+    pub fn undo(&mut self) -> Option<u8> {
+        let col = self.moves.pop()?;
+        self.heights[col as usize] -= 1;
+        let bit =
+            1 << (col as usize * self.config.col_stride() + self.heights[col as usize] as usize);
+        let color = if self.red & bit != 0 {
+            self.red &= !bit;
+            Color::Red
+        } else {
+            self.yellow &= !bit;
+            Color::Yellow
+        };
+        if matches!(self.winner, Some((_, ply)) if ply > self.moves.len()) {
+            self.winner = None;
         }
-        // check for diagonal wins
-        for row in 0..3 {
-            for col in 3..7 {
-                if board[row][col] != 0
-                    && board[row][col] == board[row + 1][col - 1]
-                    && board[row][col] == board[row + 2][col - 2]
-                    && board[row][col] == board[row + 3][col - 3]
-                {
-                    return match board[row][col] {
-                        1 => GameResult::RedWin,
-                        2 => GameResult::YellowWin,
-                        _ => panic!("Invalid board state"),
-                    };
-                }
-            }
+        self.undone.push((col, color));
+        Some(col)
+    }
+
+    /// Plays the last undone move again and returns its column.
+    pub fn redo(&mut self) -> Option<u8> {
+        let (col, color) = self.undone.pop()?;
+        self.place(col, color).unwrap();
+        Some(col)
+    }
+
+    /// Every column played so far, in order.
+    pub fn moves(&self) -> &[u8] {
+        &self.moves
+    }
+
+    pub fn last_move(&self) -> Option<u8> {
+        self.moves.last().copied()
+    }
+
+    /// Whose turn it is, given that red moves first.
+    pub fn to_move(&self) -> Color {
+        if (self.red | self.yellow).count_ones().is_multiple_of(2) {
+            Color::Red
+        } else {
+            Color::Yellow
         }
-        // check for draw
-        for row in 0..6 {
-            for col in 0..7 {
-                if board[row][col] == 0 {
-                    return GameResult::InProgress;
-                }
-            }
+    }
+
+    fn place(&mut self, col: u8, color: Color) -> Result<(), MoveError> {
+        self.legal_move(col)?;
+        let col = col as usize;
+        let row = self.heights[col] as usize;
+        let bit = 1 << (col * self.config.col_stride() + row);
+        let pieces = match color {
+            Color::Red => &mut self.red,
+            Color::Yellow => &mut self.yellow,
+        };
+        *pieces |= bit;
+        let pieces = *pieces;
+        self.heights[col] += 1;
+        self.moves.push(col as u8);
+        if self.winner.is_none() && self.connects(pieces, col, row) {
+            self.winner = Some((color, self.moves.len()));
         }
-        // if we get here, the board is full and there are no wins
-        GameResult::Draw
+        Ok(())
     }
 
-    pub fn play_move(&mut self, col: u8, piece: i32) -> Result<(), ()> {
-        let board = &mut self.0;
-        if col > 6 {
-            return Err(());
+    pub fn legal_move(&self, col: u8) -> Result<(), MoveError> {
+        if self.winner.is_some() {
+            return Err(MoveError::GameOver);
         }
-        for row in (0..6).rev() {
-            if board[row as usize][col as usize] == 0 {
-                board[row as usize][col as usize] = piece;
-                return Ok(());
-            }
+        match self.heights.get(col as usize) {
+            Some(&height) if height < self.config.rows => Ok(()),
+            Some(_) => Err(MoveError::ColumnFull),
+            None => Err(MoveError::OutOfRange),
         }
-        Err(())
     }
 
-    pub fn legal_move(&mut self, col: u8) -> Result<(), ()> {
-        let board = self.0;
-        if col > 6 {
-            return Err(());
+    /// Builds a standard board from a move sequence such as `"4453"`. See `from_moves_with_config`.
+    pub fn from_moves(moves: &str) -> Result<Self, NotationError> {
+        Self::from_moves_with_config(BoardConfig::STANDARD, moves)
+    }
+
+    /// Builds a board by playing `moves` from an empty board, red first.
+    ///
+    /// Each character is a 1-indexed column, so `"4453"` means two pieces in the middle column of
+    /// a standard board, then columns 5 and 3. Boards wider than 9 columns continue with `a`-`z`,
+    /// which reaches column 35, so the columns past that cannot be written down.
+    pub fn from_moves_with_config(config: BoardConfig, moves: &str) -> Result<Self, NotationError> {
+        let mut board = Board::with_config(config);
+        for (index, found) in moves.chars().enumerate() {
+            let col = match found.to_digit(36) {
+                Some(digit) if digit > 0 => digit as u8 - 1,
+                _ => return Err(NotationError::InvalidCharacter { index, found }),
+            };
+            board
+                .play_move(col, board.to_move())
+                .map_err(|error| NotationError::IllegalMove {
+                    index,
+                    column: found,
+                    error,
+                })?;
         }
-        for row in (0..6).rev() {
-            if board[row as usize][col as usize] == 0 {
-                return Ok(());
+        Ok(board)
+    }
+
+    /// Writes the moves played so far in the notation `from_moves` reads, or `None` if one of them
+    /// is past the 35th column, which the notation has no character for.
+    pub fn to_moves(&self) -> Option<String> {
+        self.moves
+            .iter()
+            .map(|&col| std::char::from_digit(col as u32 + 1, 36))
+            .collect()
+    }
+
+    /// Every cell, row by row from the top left, in the order `from_cells` reads them.
+    pub fn cells(&self) -> Vec<Cell> {
+        (0..self.config.rows)
+            .flat_map(|row| (0..self.config.cols).map(move |col| (row, col)))
+            .map(|(row, col)| self.cell(row, col).unwrap())
+            .collect()
+    }
+
+    /// Builds a board from a grid like the one `cells` returns. Only the position is known, so
+    /// the board has no moves to undo.
+    pub fn from_cells(config: BoardConfig, cells: &[Cell]) -> Result<Self, GridError> {
+        let (rows, cols) = (config.rows as usize, config.cols as usize);
+        if cells.len() != rows * cols {
+            return Err(GridError::WrongSize);
+        }
+        let mut board = Board::with_config(config);
+        for col in 0..cols {
+            for row in 0..rows {
+                let bit = 1 << (col * config.col_stride() + row);
+                let pieces = match cells[(rows - 1 - row) * cols + col] {
+                    Cell::Empty => continue,
+                    Cell::Piece(_) if board.heights[col] as usize != row => {
+                        return Err(GridError::FloatingPiece)
+                    }
+                    Cell::Piece(Color::Red) => &mut board.red,
+                    Cell::Piece(Color::Yellow) => &mut board.yellow,
+                };
+                *pieces |= bit;
+                board.heights[col] += 1;
             }
         }
-        Err(())
+        let (red, yellow) = (board.red.count_ones(), board.yellow.count_ones());
+        if red != yellow && red != yellow + 1 {
+            return Err(GridError::PieceCount);
+        }
+        let wins = |pieces: u128| {
+            (0..cols).any(|col| {
+                (0..board.heights[col] as usize).any(|row| {
+                    board.occupied(pieces, col as isize, row as isize)
+                        && board.connects(pieces, col, row)
+                })
+            })
+        };
+        board.winner = match (wins(board.red), wins(board.yellow)) {
+            (true, true) => return Err(GridError::TwoWinners),
+            (true, false) => Some((Color::Red, 0)),
+            (false, true) => Some((Color::Yellow, 0)),
+            (false, false) => None,
+        };
+        Ok(board)
+    }
+
+    /// Returns what is at `row`, counted from the top like the display, and `col`, or `None` if
+    /// that square is off the board.
+    pub fn cell(&self, row: u8, col: u8) -> Option<Cell> {
+        if row >= self.config.rows || col >= self.config.cols {
+            return None;
+        }
+        let bit =
+            1 << (col as usize * self.config.col_stride() + (self.config.rows - 1 - row) as usize);
+        Some(if self.red & bit != 0 {
+            Cell::Piece(Color::Red)
+        } else if self.yellow & bit != 0 {
+            Cell::Piece(Color::Yellow)
+        } else {
+            Cell::Empty
+        })
+    }
+
+    /// Checks the four lines through the piece at (`col`, `row`) for a winning run.
+    fn connects(&self, pieces: u128, col: usize, row: usize) -> bool {
+        DIRECTIONS.iter().any(|&(dc, dr)| {
+            1 + self.run(pieces, col, row, dc, dr) + self.run(pieces, col, row, -dc, -dr)
+                >= self.config.connect as usize
+        })
+    }
+
+    /// Counts the pieces in `pieces` that continue from (`col`, `row`) in direction (`dc`, `dr`).
+    fn run(&self, pieces: u128, col: usize, row: usize, dc: isize, dr: isize) -> usize {
+        let (col, row) = (col as isize, row as isize);
+        (1..)
+            .take_while(|&i| self.occupied(pieces, col + dc * i, row + dr * i))
+            .count()
+    }
+
+    /// Whether `pieces` has a piece at (`col`, `row`), counting rows from the bottom. Squares off
+    /// the board are never occupied.
+    fn occupied(&self, pieces: u128, col: isize, row: isize) -> bool {
+        (0..self.config.cols as isize).contains(&col)
+            && (0..self.config.rows as isize).contains(&row)
+            && pieces & (1 << (col as usize * self.config.col_stride() + row as usize)) != 0
     }
 }
 
 impl Display for Board {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        let board = self.0;
-        write!(f, " 0 1 2 3 4 5 6\n")?;
-        for row in 0..6 {
-            for col in 0..7 {
-                match board[row][col] {
-                    0 => write!(f, "⚪")?,
-                    1 => write!(f, "🔴")?,
-                    2 => write!(f, "🟡")?,
-                    _ => panic!("Invalid board state"),
+        self.highlight(&[]).fmt(f)
+    }
+}
+
+/// A board rendered with some of its cells highlighted. See `Board::highlight`.
+pub struct Highlighted<'a> {
+    board: &'a Board,
+    cells: &'a [(u8, u8)],
+}
+
+impl Display for Highlighted<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let config = self.board.config;
+        for col in 0..config.cols {
+            write!(f, "{:>2}", col)?;
+        }
+        writeln!(f)?;
+        for row in 0..config.rows {
+            for col in 0..config.cols {
+                let highlighted = self.cells.contains(&(row, col));
+                match self.board.cell(row, col) {
+                    Some(Cell::Piece(Color::Red)) if highlighted => write!(f, "🟥")?,
+                    Some(Cell::Piece(Color::Yellow)) if highlighted => write!(f, "🟨")?,
+                    Some(Cell::Piece(Color::Red)) => write!(f, "🔴")?,
+                    Some(Cell::Piece(Color::Yellow)) => write!(f, "🟡")?,
+                    _ => write!(f, "⚪")?,
                 }
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
         Ok(())
     }
 }
+
+#[test]
+fn test_board_score() {
+    let play = |moves: &[u8]| {
+        let mut board = Board::new();
+        for &col in moves {
+            board.play_move(col, board.to_move()).unwrap();
+        }
+        board
+    };
+    assert_eq!(play(&[]).score(), GameResult::InProgress);
+    // horizontal, vertical and both diagonals
+    assert_eq!(play(&[0, 0, 1, 1, 2, 2, 3]).score(), GameResult::RedWin);
+    assert_eq!(
+        play(&[0, 1, 0, 1, 0, 1, 6, 1]).score(),
+        GameResult::YellowWin
+    );
+    assert_eq!(
+        play(&[0, 1, 1, 2, 2, 3, 2, 3, 3, 6, 3]).score(),
+        GameResult::RedWin
+    );
+    assert_eq!(
+        play(&[6, 5, 5, 4, 4, 3, 4, 3, 3, 0, 3]).score(),
+        GameResult::RedWin
+    );
+    // a full board with no four in a row
+    let moves: Vec<u8> = "545062455041104565311226266362030334314210"
+        .bytes()
+        .map(|b| b - b'0')
+        .collect();
+    assert_eq!(play(&moves[..41]).score(), GameResult::InProgress);
+    assert_eq!(play(&moves).score(), GameResult::Draw);
+
+    let mut board = Board::new();
+    let (red, yellow) = (Color::Red, Color::Yellow);
+    for color in [red, red, yellow, yellow, red, red] {
+        board.play_move(3, color).unwrap();
+    }
+    assert_eq!(board.legal_move(3), Err(MoveError::ColumnFull));
+    assert_eq!(board.play_move(3, yellow), Err(MoveError::ColumnFull));
+    assert_eq!(board.play_move(7, yellow), Err(MoveError::OutOfRange));
+    assert_eq!(board.cell(0, 3), Some(Cell::Piece(red)));
+    assert_eq!(board.cell(2, 3), Some(Cell::Piece(yellow)));
+    assert_eq!(board.cell(0, 4), Some(Cell::Empty));
+    assert_eq!(board.cell(6, 0), None);
+    assert_eq!(board.cell(0, 7), None);
+}
+
+#[test]
+fn test_board_winning_lines() {
+    let board = Board::from_moves("1324").unwrap();
+    assert!(board.winning_lines().is_empty());
+
+    let board = Board::from_moves("1122334").unwrap();
+    assert_eq!(
+        board.winning_lines(),
+        vec![vec![(5, 0), (5, 1), (5, 2), (5, 3)]]
+    );
+    // one move can finish a vertical and a diagonal line at once
+    let board = Board::from_moves("6565513764436").unwrap();
+    assert_eq!(board.score(), GameResult::RedWin);
+    assert_eq!(
+        board.winning_lines(),
+        vec![
+            vec![(2, 5), (3, 5), (4, 5), (5, 5)],
+            vec![(5, 2), (4, 3), (3, 4), (2, 5)],
+        ]
+    );
+    // filling the gap in the middle of a row wins with all five pieces
+    let board = Board::from_moves("112244553").unwrap();
+    assert_eq!(
+        board.winning_lines(),
+        vec![vec![(5, 0), (5, 1), (5, 2), (5, 3), (5, 4)]]
+    );
+    // lines go away with the move that made them
+    let mut board = board;
+    board.undo();
+    assert!(board.winning_lines().is_empty());
+
+    let lines = Board::from_moves("1122334").unwrap().winning_lines();
+    let rendered = Board::from_moves("1122334")
+        .unwrap()
+        .highlight(&lines[0])
+        .to_string();
+    assert!(rendered.ends_with("🟥🟥🟥🟥⚪⚪⚪\n"));
+}
+
+#[test]
+fn test_board_cells() {
+    for moves in ["", "4453", "1122334", "6565513764436"] {
+        let board = Board::from_moves(moves).unwrap();
+        let rebuilt = Board::from_cells(board.config(), &board.cells()).unwrap();
+        assert_eq!(rebuilt.cells(), board.cells());
+        assert_eq!(rebuilt.score(), board.score());
+        assert_eq!(rebuilt.to_move(), board.to_move());
+        assert_eq!(rebuilt.winning_lines(), board.winning_lines());
+        assert!(rebuilt.moves().is_empty());
+    }
+    let mut board = Board::from_cells(
+        BoardConfig::STANDARD,
+        &Board::from_moves("44").unwrap().cells(),
+    )
+    .unwrap();
+    board.play_move(3, Color::Red).unwrap();
+    assert_eq!(board.cell(3, 3), Some(Cell::Piece(Color::Red)));
+
+    let config = BoardConfig::new(2, 2, 2).unwrap();
+    let (e, r, y) = (
+        Cell::Empty,
+        Cell::Piece(Color::Red),
+        Cell::Piece(Color::Yellow),
+    );
+    assert_eq!(
+        Board::from_cells(config, &[e; 3]),
+        Err(GridError::WrongSize)
+    );
+    assert_eq!(
+        Board::from_cells(config, &[r, e, e, y]),
+        Err(GridError::FloatingPiece)
+    );
+    assert_eq!(
+        Board::from_cells(config, &[e, e, y, e]),
+        Err(GridError::PieceCount)
+    );
+    assert_eq!(
+        Board::from_cells(config, &[r, y, r, y]),
+        Err(GridError::TwoWinners)
+    );
+    assert_eq!(
+        Board::from_cells(config, &[e, e, r, r]).map(|board| board.score()),
+        Err(GridError::PieceCount)
+    );
+    let board = Board::from_cells(config, &[r, e, r, y]).unwrap();
+    assert_eq!(board.score(), GameResult::RedWin);
+    assert_eq!(board.winning_lines(), vec![vec![(0, 0), (1, 0)]]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_board_serde() {
+    let board = Board::from_moves("4453").unwrap();
+    let json = serde_json::to_value(&board).unwrap();
+    assert_eq!(json["moves"], serde_json::json!([3, 3, 4, 2]));
+    assert_eq!(
+        json["config"],
+        serde_json::json!({"rows": 6, "cols": 7, "connect": 4})
+    );
+    assert_eq!(json["cells"][38], serde_json::json!({"piece": "red"}));
+    let parsed: Board = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed, board);
+
+    // history is optional, but has to agree with the grid when both are given
+    let parsed: Board =
+        serde_json::from_str(r#"{"config": {"rows": 6, "cols": 7, "connect": 4}, "moves": [3]}"#)
+            .unwrap();
+    assert_eq!(parsed.moves(), &[3]);
+    let grid = Board::from_cells(BoardConfig::STANDARD, &board.cells()).unwrap();
+    let parsed: Board = serde_json::from_value(serde_json::to_value(&grid).unwrap()).unwrap();
+    assert_eq!(parsed.cells(), board.cells());
+    let mut json = serde_json::to_value(&board).unwrap();
+    json["moves"] = serde_json::json!([0, 0, 0, 0]);
+    assert!(serde_json::from_value::<Board>(json).is_err());
+    assert!(
+        serde_json::from_str::<BoardConfig>(r#"{"rows": 0, "cols": 7, "connect": 4}"#).is_err()
+    );
+
+    let packet = ServerBoundPacket::Init {
+        name: "Blechdavier".to_string(),
+        bot: Some(Difficulty::Hard),
+        version: PROTOCOL_VERSION,
+        capabilities: Capabilities::CHAT,
+    };
+    assert_eq!(
+        serde_json::to_value(&packet).unwrap(),
+        serde_json::json!({
+            "type": "init",
+            "name": "Blechdavier",
+            "bot": "hard",
+            "version": PROTOCOL_VERSION,
+            "capabilities": 1,
+        })
+    );
+    let packet = ServerBoundPacket::Resume {
+        token: u64::MAX - 1,
+    };
+    let json = serde_json::to_string(&packet).unwrap();
+    assert_eq!(json, r#"{"type":"resume","token":"18446744073709551614"}"#);
+    assert_eq!(
+        serde_json::from_str::<ServerBoundPacket>(&json).unwrap(),
+        packet
+    );
+}
+
+#[test]
+fn test_board_config() {
+    assert_eq!(BoardConfig::new(0, 7, 4), Err(ConfigError::Empty));
+    assert_eq!(BoardConfig::new(6, 7, 8), Err(ConfigError::InvalidConnect));
+    assert_eq!(BoardConfig::new(15, 9, 4), Err(ConfigError::TooLarge));
+    assert!(BoardConfig::new(7, 8, 4).is_ok());
+
+    // connect 5 on a 9x9 board: four in a row is not enough
+    let mut board = Board::with_config(BoardConfig::new(9, 9, 5).unwrap());
+    for col in [0, 0, 1, 1, 2, 2, 3, 3] {
+        board
+            .play_move(
+                col,
+                if board.heights[col as usize] == 0 {
+                    Color::Red
+                } else {
+                    Color::Yellow
+                },
+            )
+            .unwrap();
+    }
+    assert_eq!(board.score(), GameResult::InProgress);
+    board.play_move(8, Color::Yellow).unwrap();
+    board.play_move(4, Color::Red).unwrap();
+    assert_eq!(board.score(), GameResult::RedWin);
+    assert!(board.legal_move(9).is_err());
+    assert!(board.to_string().starts_with(" 0 1 2 3 4 5 6 7 8\n"));
+
+    // the top of a tall column must not wrap into the next one
+    let mut board = Board::with_config(BoardConfig::new(7, 8, 4).unwrap());
+    let (red, yellow) = (Color::Red, Color::Yellow);
+    for color in [yellow, yellow, red, yellow, yellow, red, red] {
+        board.play_move(0, color).unwrap();
+    }
+    board.play_move(1, red).unwrap();
+    board.play_move(1, red).unwrap();
+    assert_eq!(board.score(), GameResult::InProgress);
+}
+
+#[test]
+fn test_board_history() {
+    let mut board = Board::new();
+    assert_eq!(board.undo(), None);
+    assert_eq!(board.last_move(), None);
+    for col in [3, 3, 4, 4, 5, 5, 6] {
+        board.play_move(col, board.to_move()).unwrap();
+    }
+    assert_eq!(board.moves(), &[3, 3, 4, 4, 5, 5, 6]);
+    assert_eq!(board.last_move(), Some(6));
+    assert_eq!(board.score(), GameResult::RedWin);
+
+    // taking back the winning move reopens the game
+    assert_eq!(board.undo(), Some(6));
+    assert_eq!(board.score(), GameResult::InProgress);
+    assert_eq!(board.undo(), Some(5));
+    assert_eq!(board.moves(), &[3, 3, 4, 4, 5]);
+    assert_eq!(board.redo(), Some(5));
+    assert_eq!(board.redo(), Some(6));
+    assert_eq!(board.redo(), None);
+    assert_eq!(board.score(), GameResult::RedWin);
+
+    // undoing everything gets back to an empty board
+    let mut replay = board.clone();
+    while replay.undo().is_some() {}
+    let mut empty = Board::new();
+    assert_eq!(replay.to_string(), empty.to_string());
+    assert_eq!(replay.moves(), empty.moves());
+
+    // a new move drops whatever could have been redone
+    board.undo();
+    board.undo();
+    board.play_move(0, Color::Yellow).unwrap();
+    assert_eq!(board.redo(), None);
+    assert_eq!(board.moves(), &[3, 3, 4, 4, 5, 0]);
+    empty.play_move(0, Color::Red).unwrap();
+    assert_eq!(empty.undo(), Some(0));
+    assert_eq!(empty.redo(), Some(0));
+    assert_eq!(empty.to_string().lines().last(), Some("🔴⚪⚪⚪⚪⚪⚪"));
+}
+
+#[test]
+fn test_board_notation() {
+    let board = Board::from_moves("4453").unwrap();
+    assert_eq!(board.moves(), &[3, 3, 4, 2]);
+    assert_eq!(board.to_moves().as_deref(), Some("4453"));
+    assert_eq!(
+        Board::from_moves("").unwrap().to_moves().as_deref(),
+        Some("")
+    );
+    assert_eq!(
+        Board::from_moves("1212121").unwrap().score(),
+        GameResult::RedWin
+    );
+
+    assert_eq!(
+        Board::from_moves("4408").unwrap_err(),
+        NotationError::InvalidCharacter {
+            index: 2,
+            found: '0'
+        }
+    );
+    assert_eq!(
+        Board::from_moves("44-").unwrap_err(),
+        NotationError::InvalidCharacter {
+            index: 2,
+            found: '-'
+        }
+    );
+    assert_eq!(
+        Board::from_moves("448").unwrap_err(),
+        NotationError::IllegalMove {
+            index: 2,
+            column: '8',
+            error: MoveError::OutOfRange
+        }
+    );
+    let err = Board::from_moves("4444444").unwrap_err();
+    assert_eq!(
+        err,
+        NotationError::IllegalMove {
+            index: 6,
+            column: '4',
+            error: MoveError::ColumnFull
+        }
+    );
+    assert_eq!(err.to_string(), "move 7 (column 4): column is full");
+    assert_eq!(
+        Board::from_moves("12121212").unwrap_err(),
+        NotationError::IllegalMove {
+            index: 7,
+            column: '2',
+            error: MoveError::GameOver
+        }
+    );
+
+    // wide boards keep counting with letters
+    let config = BoardConfig::new(4, 12, 4).unwrap();
+    let board = Board::from_moves_with_config(config, "ac1").unwrap();
+    assert_eq!(board.moves(), &[9, 11, 0]);
+    assert_eq!(board.to_moves().as_deref(), Some("ac1"));
+
+    // up to the 35th column of the widest boards
+    let config = BoardConfig::new(2, 42, 4).unwrap();
+    let mut board = Board::from_moves_with_config(config, "z1y").unwrap();
+    assert_eq!(board.moves(), &[34, 0, 33]);
+    let moves = board.to_moves().unwrap();
+    assert_eq!(moves, "z1y");
+    assert_eq!(
+        Board::from_moves_with_config(config, &moves)
+            .unwrap()
+            .moves(),
+        board.moves()
+    );
+    board.play_move(41, board.to_move()).unwrap();
+    assert_eq!(board.to_moves(), None);
+}
@@ -0,0 +1,661 @@
+//! Exact game-theoretic solver for any `BoardConfig`.
+//!
+//! The search is a negamax with alpha-beta pruning over the same bitboards `Board` uses. Moves are
+//! tried center first, then by how many winning spots they create, and every searched position
+//! caches an upper bound on its score in a transposition table. The root score is narrowed down
+//! with null-window searches.
+//!
+//! Scores follow the usual convention for Connect 4 solvers: 0 is a draw, and a win is worth half
+//! the number of empty cells left before the winning piece is played, rounded up. Positive scores
+//! are wins for the side to move and negative ones are losses, so on a standard board a score can
+//! be compared directly with the published benchmark sets.
+
+use crate::{Board, BoardConfig, Color, GameResult};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// How the game ends for the side to move with perfect play from both sides.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Outcome {
+    Win,
+    Loss,
+    Draw,
+}
+
+/// The exact value of a position for the side to move.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Evaluation {
+    pub outcome: Outcome,
+    /// Number of moves, counting both sides, until the game ends with perfect play.
+    pub plies: u32,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Solution {
+    pub evaluation: Evaluation,
+    /// The raw negamax score the evaluation was derived from. See the module docs.
+    pub score: i32,
+    /// A move that achieves the evaluation, preferring the center.
+    pub best_move: Option<u8>,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum SolveError {
+    /// The position is already won or the board is full.
+    GameOver,
+}
+
+impl Display for SolveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SolveError::GameOver => write!(f, "the game is already over"),
+        }
+    }
+}
+
+impl Error for SolveError {}
+
+/// A solver keeps its transposition table between calls, so solving several positions from the
+/// same game is much cheaper than starting from scratch each time.
+pub struct Solver {
+    table: Vec<(u128, i8)>,
+    config: Option<BoardConfig>,
+    nodes: u64,
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Solver {
+    pub fn new() -> Self {
+        Self::with_table_size(1 << 20)
+    }
+
+    /// Creates a solver whose transposition table holds `entries` positions.
+    pub fn with_table_size(entries: usize) -> Self {
+        Solver {
+            table: vec![(0, 0); entries.max(1)],
+            config: None,
+            nodes: 0,
+        }
+    }
+
+    /// Number of positions searched since the solver was created.
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    pub fn solve(&mut self, board: &Board, to_move: Color) -> Result<Solution, SolveError> {
+        if board.score() != GameResult::InProgress {
+            return Err(SolveError::GameOver);
+        }
+        // cached bounds are only meaningful for the board shape they were computed on
+        if self.config != Some(board.config) {
+            self.table.iter_mut().for_each(|entry| *entry = (0, 0));
+            self.config = Some(board.config);
+        }
+        let position = Position::new(board, to_move);
+        let score = self.score(&position);
+
+        let mut best_move = None;
+        for col in position.geometry.column_order() {
+            if !position.can_play(col) {
+                continue;
+            }
+            let mov = position.move_bit(col);
+            // the root score is reached by every move that keeps the child at or below -score
+            let reached = if position.is_winning_move(mov) {
+                position.win_score()
+            } else {
+                let child = position.play(mov);
+                -self.bounded(&child, -score, -score + 1)
+            };
+            if reached >= score {
+                best_move = Some(col as u8);
+                break;
+            }
+        }
+
+        Ok(Solution {
+            evaluation: position.evaluation(score),
+            score,
+            best_move,
+        })
+    }
+
+    /// Finds the exact score of `position` by repeatedly halving the window it can lie in.
+    fn score(&mut self, position: &Position) -> i32 {
+        let cells = position.geometry.cells as i32;
+        let moves = position.moves as i32;
+        let mut min = -(cells - moves) / 2;
+        let mut max = (cells + 1 - moves) / 2;
+        while min < max {
+            let mut med = min + (max - min) / 2;
+            if med <= 0 && min / 2 < med {
+                med = min / 2;
+            } else if med >= 0 && max / 2 > med {
+                med = max / 2;
+            }
+            let r = self.bounded(position, med, med + 1);
+            if r <= med {
+                max = r;
+            } else {
+                min = r;
+            }
+        }
+        min
+    }
+
+    /// Like `negamax`, but also accepts positions where the side to move can win immediately or
+    /// where no move is left.
+    fn bounded(&mut self, position: &Position, alpha: i32, beta: i32) -> i32 {
+        if position.moves == position.geometry.cells {
+            0
+        } else if position.can_win_next() {
+            position.win_score()
+        } else {
+            self.negamax(position, alpha, beta)
+        }
+    }
+
+    /// Scores `position` within (`alpha`, `beta`). The side to move must not have a winning move.
+    fn negamax(&mut self, position: &Position, mut alpha: i32, mut beta: i32) -> i32 {
+        self.nodes += 1;
+        let cells = position.geometry.cells as i32;
+        let moves = position.moves as i32;
+
+        let next = position.non_losing_moves();
+        if next == 0 {
+            // every move lets the opponent win right after
+            return -(cells - moves) / 2;
+        }
+        if moves >= cells - 2 {
+            // neither side can win with the last two pieces
+            return 0;
+        }
+
+        // the opponent cannot win with their next piece, so we lose at the earliest after that
+        let min = -(cells - 2 - moves) / 2;
+        if alpha < min {
+            alpha = min;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+        // we cannot win with our next piece, so we win at the earliest with the one after
+        let mut max = (cells - 1 - moves) / 2;
+        if let Some(bound) = self.lookup(position.key()) {
+            max = bound;
+        }
+        if beta > max {
+            beta = max;
+            if alpha >= beta {
+                return beta;
+            }
+        }
+
+        // a board has at most 64 columns, see `BoardConfig::new`
+        let mut ordered = [(0u32, 0u128); 64];
+        let mut len = 0;
+        for col in position.geometry.column_order() {
+            let mov = next & position.geometry.column_mask(col);
+            if mov != 0 {
+                let threats = position.threats_after(mov);
+                // stable insertion keeps the center-first order between equal threat counts
+                let mut at = len;
+                while at > 0 && ordered[at - 1].0 < threats {
+                    ordered[at] = ordered[at - 1];
+                    at -= 1;
+                }
+                ordered[at] = (threats, mov);
+                len += 1;
+            }
+        }
+
+        for &(_, mov) in &ordered[..len] {
+            let score = -self.negamax(&position.play(mov), -beta, -alpha);
+            if score >= beta {
+                return score;
+            }
+            if score > alpha {
+                alpha = score;
+            }
+        }
+        self.store(position.key(), alpha);
+        alpha
+    }
+
+    /// Spreads keys over the table. The low bits of a key only describe the first few columns,
+    /// so they have to be mixed with the rest before being reduced to an index.
+    fn index(&self, key: u128) -> usize {
+        let folded = (key as u64) ^ ((key >> 64) as u64);
+        ((folded.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) % self.table.len() as u64) as usize
+    }
+
+    fn lookup(&self, key: u128) -> Option<i32> {
+        let (stored, bound) = self.table[self.index(key)];
+        (stored == key && key != 0).then_some(bound as i32)
+    }
+
+    fn store(&mut self, key: u128, bound: i32) {
+        let index = self.index(key);
+        self.table[index] = (key, bound as i8);
+    }
+}
+
+/// Masks that only depend on the board shape.
+#[derive(Clone, Copy)]
+struct Geometry {
+    rows: usize,
+    cols: usize,
+    connect: usize,
+    stride: usize,
+    cells: u32,
+    bottom: u128,
+    playable: u128,
+}
+
+impl Geometry {
+    fn new(config: BoardConfig) -> Self {
+        let rows = config.rows as usize;
+        let cols = config.cols as usize;
+        let stride = config.col_stride();
+        let column = (1u128 << rows) - 1;
+        let mut bottom = 0;
+        let mut playable = 0;
+        for col in 0..cols {
+            bottom |= 1 << (col * stride);
+            playable |= column << (col * stride);
+        }
+        Geometry {
+            rows,
+            cols,
+            connect: config.connect as usize,
+            stride,
+            cells: (rows * cols) as u32,
+            bottom,
+            playable,
+        }
+    }
+
+    fn column_mask(&self, col: usize) -> u128 {
+        ((1u128 << self.rows) - 1) << (col * self.stride)
+    }
+
+    /// Columns ordered from the center outwards.
+    fn column_order(&self) -> impl Iterator<Item = usize> {
+        let cols = self.cols;
+        (0..cols).map(move |i| {
+            if i % 2 == 0 {
+                cols / 2 + i.div_ceil(2)
+            } else {
+                cols / 2 - i.div_ceil(2)
+            }
+        })
+    }
+}
+
+fn shl(bits: u128, by: usize) -> u128 {
+    if by >= 128 {
+        0
+    } else {
+        bits << by
+    }
+}
+
+fn shr(bits: u128, by: usize) -> u128 {
+    if by >= 128 {
+        0
+    } else {
+        bits >> by
+    }
+}
+
+/// A position from the point of view of the side to move.
+struct Position {
+    geometry: Geometry,
+    current: u128,
+    mask: u128,
+    moves: u32,
+}
+
+impl Position {
+    fn new(board: &Board, to_move: Color) -> Self {
+        let mask = board.red | board.yellow;
+        Position {
+            geometry: Geometry::new(board.config),
+            current: match to_move {
+                Color::Red => board.red,
+                Color::Yellow => board.yellow,
+            },
+            mask,
+            moves: mask.count_ones(),
+        }
+    }
+
+    /// Unique among positions of the same shape: the carry of `mask + current` in each column
+    /// lands just above its top piece.
+    fn key(&self) -> u128 {
+        self.current + self.mask
+    }
+
+    fn can_play(&self, col: usize) -> bool {
+        self.possible() & self.geometry.column_mask(col) != 0
+    }
+
+    fn move_bit(&self, col: usize) -> u128 {
+        self.possible() & self.geometry.column_mask(col)
+    }
+
+    fn play(&self, mov: u128) -> Position {
+        Position {
+            geometry: self.geometry,
+            current: self.current ^ self.mask,
+            mask: self.mask | mov,
+            moves: self.moves + 1,
+        }
+    }
+
+    /// The lowest empty cell of every column that is not full.
+    fn possible(&self) -> u128 {
+        (self.mask + self.geometry.bottom) & self.geometry.playable
+    }
+
+    fn is_winning_move(&self, mov: u128) -> bool {
+        self.winning_cells(self.current) & mov != 0
+    }
+
+    fn can_win_next(&self) -> bool {
+        self.winning_cells(self.current) & self.possible() != 0
+    }
+
+    /// Score for winning with the next piece.
+    fn win_score(&self) -> i32 {
+        (self.geometry.cells as i32 + 1 - self.moves as i32) / 2
+    }
+
+    /// Moves that do not hand the opponent an immediate win.
+    fn non_losing_moves(&self) -> u128 {
+        let mut possible = self.possible();
+        let opponent_wins = self.winning_cells(self.current ^ self.mask);
+        let forced = possible & opponent_wins;
+        if forced != 0 {
+            if forced & (forced - 1) != 0 {
+                // the opponent has two winning moves and we can only block one
+                return 0;
+            }
+            possible = forced;
+        }
+        // never play directly below a cell that wins for the opponent
+        possible & !(opponent_wins >> 1)
+    }
+
+    /// Number of empty cells that would win for us after playing `mov`.
+    fn threats_after(&self, mov: u128) -> u32 {
+        let pieces = self.current | mov;
+        let empty = self.geometry.playable & !(self.mask | mov);
+        (self.winning_cells(pieces) & empty).count_ones()
+    }
+
+    /// Empty cells that would complete a line of `pieces`.
+    fn winning_cells(&self, pieces: u128) -> u128 {
+        let g = &self.geometry;
+        let k = g.connect;
+        // vertical lines can only be completed from the top
+        let mut cells = (1..k).fold(!0, |acc, i| acc & shl(pieces, i));
+        // horizontal, and both diagonals, with `left` pieces on one side of the empty cell
+        for step in [g.stride, g.stride - 1, g.stride + 1] {
+            for left in 0..k {
+                let mut line = !0;
+                for i in 1..=left {
+                    line &= shl(pieces, i * step);
+                }
+                for i in 1..k - left {
+                    line &= shr(pieces, i * step);
+                }
+                cells |= line;
+            }
+        }
+        cells & g.playable & !self.mask
+    }
+
+    fn evaluation(&self, score: i32) -> Evaluation {
+        let cells = self.geometry.cells as i32;
+        let moves = self.moves as i32;
+        // the winner places its last piece onto a board holding `before` pieces, and the parity
+        // of `before` tells whose turn it is
+        let plies_to_win = |winner_parity: i32, score: i32| {
+            let before = if (cells + 1 - 2 * score - winner_parity) % 2 == 0 {
+                cells + 1 - 2 * score
+            } else {
+                cells - 2 * score
+            };
+            (before - moves + 1) as u32
+        };
+        match score {
+            0 => Evaluation {
+                outcome: Outcome::Draw,
+                plies: (cells - moves) as u32,
+            },
+            s if s > 0 => Evaluation {
+                outcome: Outcome::Win,
+                plies: plies_to_win(moves % 2, s),
+            },
+            s => Evaluation {
+                outcome: Outcome::Loss,
+                plies: plies_to_win((moves + 1) % 2, -s),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+fn play_all(config: BoardConfig, moves: &[u8]) -> Board {
+    let mut board = Board::with_config(config);
+    for &col in moves {
+        board.play_move(col, board.to_move()).unwrap();
+    }
+    board
+}
+
+/// Plain minimax over `Board`, scored the same way as the solver.
+#[cfg(test)]
+fn brute_force(board: &Board, color: Color) -> i32 {
+    let config = board.config();
+    let cells = config.rows() as i32 * config.cols() as i32;
+    let moves = (board.red | board.yellow).count_ones() as i32;
+    let mut best = None;
+    for col in 0..config.cols() {
+        let mut child = board.clone();
+        if child.play_move(col, color).is_err() {
+            continue;
+        }
+        let score = match child.score() {
+            GameResult::InProgress => -brute_force(&child, color.opponent()),
+            GameResult::Draw => 0,
+            _ => (cells + 1 - moves) / 2,
+        };
+        best = best.max(Some(score));
+    }
+    best.unwrap_or(0)
+}
+
+#[test]
+fn test_solver_matches_brute_force() {
+    let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut next = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    let mut solver = Solver::with_table_size(1 << 16);
+    for (config, empty) in [
+        (BoardConfig::STANDARD, 10),
+        (BoardConfig::new(5, 5, 4).unwrap(), 11),
+        (BoardConfig::new(4, 4, 3).unwrap(), 12),
+    ] {
+        let cells = config.rows() as usize * config.cols() as usize;
+        let mut positions = 0;
+        while positions < 20 {
+            // random moves that do not end the game, until `empty` cells are left
+            let mut board = Board::with_config(config);
+            let mut moves = 0;
+            while moves < cells - empty {
+                let col = (next() % config.cols() as u64) as u8;
+                let mut child = board.clone();
+                if child.play_move(col, board.to_move()).is_ok()
+                    && child.score() == GameResult::InProgress
+                {
+                    board = child;
+                    moves += 1;
+                } else if (0..config.cols()).all(|col| {
+                    let mut child = board.clone();
+                    child.play_move(col, board.to_move()).is_err()
+                        || child.score() != GameResult::InProgress
+                }) {
+                    break;
+                }
+            }
+            if moves < cells - empty {
+                continue;
+            }
+            positions += 1;
+
+            let color = board.to_move();
+            let solution = solver.solve(&board, color).unwrap();
+            let expected = brute_force(&board, color);
+            assert_eq!(solution.score, expected, "{:?}\n{}", config, board);
+
+            // the suggested move must keep the score
+            let mut child = board.clone();
+            child.play_move(solution.best_move.unwrap(), color).unwrap();
+            let reached = match child.score() {
+                GameResult::InProgress => -brute_force(&child, color.opponent()),
+                GameResult::Draw => 0,
+                _ => (cells as i32 + 1 - moves as i32) / 2,
+            };
+            assert_eq!(reached, expected, "{:?}\n{}", config, board);
+        }
+    }
+}
+
+#[test]
+fn test_solver_evaluation() {
+    let mut solver = Solver::new();
+    let config = BoardConfig::STANDARD;
+
+    // red wins on the spot in column 0
+    let board = play_all(config, &[0, 1, 0, 1, 0, 1]);
+    let solution = solver.solve(&board, Color::Red).unwrap();
+    assert_eq!(
+        solution.evaluation,
+        Evaluation {
+            outcome: Outcome::Win,
+            plies: 1
+        }
+    );
+    assert_eq!(solution.best_move, Some(0));
+    assert_eq!(solution.score, 18);
+
+    // yellow can block once but red has two threats on the bottom row
+    let board = play_all(config, &[2, 2, 3, 3]);
+    let solution = solver.solve(&board, Color::Red).unwrap();
+    assert_eq!(
+        solution.evaluation,
+        Evaluation {
+            outcome: Outcome::Win,
+            plies: 3
+        }
+    );
+    assert!(matches!(solution.best_move, Some(1) | Some(4)));
+    let solution = solver
+        .solve(&play_all(config, &[2, 2, 3, 3, 4]), Color::Yellow)
+        .unwrap();
+    assert_eq!(
+        solution.evaluation,
+        Evaluation {
+            outcome: Outcome::Loss,
+            plies: 2
+        }
+    );
+
+    // a full board is a finished game
+    let moves: Vec<u8> = "545062455041104565311226266362030334314210"
+        .bytes()
+        .map(|b| b - b'0')
+        .collect();
+    assert_eq!(
+        solver.solve(&play_all(config, &moves), Color::Red),
+        Err(SolveError::GameOver)
+    );
+    let solution = solver
+        .solve(&play_all(config, &moves[..40]), Color::Red)
+        .unwrap();
+    assert_eq!(
+        solution.evaluation,
+        Evaluation {
+            outcome: Outcome::Draw,
+            plies: 2
+        }
+    );
+}
+
+/// Solves every position in `set`, a benchmark file named `name`, and checks its score. Each line
+/// is a sequence of 1-indexed columns followed by the expected score. Returns how many positions
+/// were checked.
+#[cfg(test)]
+fn check_benchmark_set(solver: &mut Solver, name: &str, set: &str) -> usize {
+    let mut checked = 0;
+    for line in set.lines() {
+        let mut parts = line.split_whitespace();
+        let (Some(moves), Some(score)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let board = Board::from_moves(moves).unwrap();
+        let color = if board.moves().len().is_multiple_of(2) {
+            Color::Red
+        } else {
+            Color::Yellow
+        };
+        let solution = solver.solve(&board, color).unwrap();
+        assert_eq!(
+            solution.score,
+            score.parse::<i32>().unwrap(),
+            "{}: {}",
+            name,
+            line
+        );
+        checked += 1;
+    }
+    checked
+}
+
+/// Lines copied from the published benchmark sets into `benches/data/sample`, so that every test
+/// run checks some of them.
+#[test]
+fn test_solver_benchmark_sample() {
+    let sample = include_str!("../benches/data/sample");
+    let checked = check_benchmark_set(&mut Solver::new(), "sample", sample);
+    assert!(checked > 0, "no benchmark positions found");
+}
+
+/// Checks the solver against the whole of the published benchmark sets (`Test_L3_R1`,
+/// `Test_L2_R2`, ...). Point `CONNECT4_BENCHMARKS` at a directory holding them and run with
+/// `--ignored`.
+#[test]
+#[ignore]
+fn test_solver_benchmark_sets() {
+    let dir = std::env::var("CONNECT4_BENCHMARKS").expect("CONNECT4_BENCHMARKS is not set");
+    let mut solver = Solver::new();
+    let mut checked = 0;
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let set = std::fs::read_to_string(&path).unwrap();
+        checked += check_benchmark_set(&mut solver, &path.display().to_string(), &set);
+    }
+    assert!(checked > 0, "no benchmark positions found");
+}
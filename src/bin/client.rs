@@ -1,144 +1,625 @@
-use connect_4::{send_packet, Board, ClientBoundPacket, Color, Deserialize, ServerBoundPacket};
-use core::panic;
+use connect_4::bot::Difficulty;
+use connect_4::codec::{self, PacketReader, PacketWriter};
+use connect_4::game::{Game, GameError};
+use connect_4::{
+    Board, Capabilities, ClientBoundPacket, Color, EndReason, ErrorCode, MoveError, ProtocolError,
+    ServerBoundPacket, DEFAULT_MAX_FRAME_SIZE, PROTOCOL_VERSION,
+};
 use std::error::Error;
+use std::fmt::Display;
 use std::io;
-use tokio::io::AsyncReadExt;
+use std::ops::ControlFlow;
+use std::time::Duration;
+use tokio::io::{ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+type Reader = PacketReader<ReadHalf<TcpStream>, ClientBoundPacket>;
+type Writer = PacketWriter<WriteHalf<TcpStream>, ServerBoundPacket>;
+/// Packets as `spawn_reader` hands them over, ending with the error that stopped it.
+type Packets = mpsc::UnboundedReceiver<io::Result<Result<ClientBoundPacket, ProtocolError>>>;
+/// Lines typed into the terminal, as `stdin_lines` hands them over.
+type Input = mpsc::UnboundedReceiver<String>;
+
+/// The optional features this client can handle, offered to the server in `Init`.
+const CAPABILITIES: Capabilities = Capabilities::VARIANTS
+    .union(Capabilities::SPECTATE)
+    .union(Capabilities::CHAT);
+
+/// What the player wants to do, picked before sending `Init`.
+enum Mode {
+    /// Whoever is next in the matchmaking queue.
+    QuickMatch,
+    /// Someone the player gives the join code of a new room to.
+    CreateRoom,
+    /// The player who opened the room with this code.
+    JoinRoom(String),
+    Bot(Difficulty),
+    /// Watch a game other players are playing.
+    Watch,
+}
+
+/// How many times to try getting back into a game after the connection drops, two seconds apart.
+const RECONNECT_ATTEMPTS: u32 = 10;
+
+const ROOM_CODE_PROMPT: &str = "What is the room code? (press enter for a quick match instead)";
+
+const HELP: &str = "Type a column number when it is your turn, or one of these:
+  /say <message>  chat with everyone in the game
+  /forfeit        give up the game
+  /refresh        redraw the board from the server's copy
+  /cancel         leave the queue, or close your room
+  /queue          join the queue again
+  /help           show this list
+  /quit           leave";
+
+/// What the next line typed answers, unless it is a command.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Prompt {
+    /// Which column to play in, when it is our turn.
+    Column,
+    /// The code of the room to join, after the last one was not found.
+    RoomCode,
+    /// Which of the listed games to watch.
+    Game,
+}
+
+/// A line typed into the client once it is connected.
+#[derive(PartialEq, Debug)]
+enum Command {
+    Say(String),
+    Forfeit,
+    Refresh,
+    Cancel,
+    Queue,
+    Help,
+    Quit,
+    /// Anything that is not a command, taken as the answer to the current `Prompt`.
+    Answer(String),
+}
+
+impl Command {
+    /// Commands start with a slash, except `forfeit` and `refresh`, which the client took before
+    /// it had any others.
+    fn parse(line: &str) -> Result<Command, String> {
+        let line = line.trim();
+        let Some(command) = line.strip_prefix('/') else {
+            return Ok(match line {
+                "forfeit" => Command::Forfeit,
+                "refresh" => Command::Refresh,
+                answer => Command::Answer(answer.to_string()),
+            });
+        };
+        let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+        match name {
+            "say" => Ok(Command::Say(rest.trim().to_string())),
+            "forfeit" => Ok(Command::Forfeit),
+            "refresh" => Ok(Command::Refresh),
+            "cancel" => Ok(Command::Cancel),
+            "queue" => Ok(Command::Queue),
+            "help" => Ok(Command::Help),
+            "quit" => Ok(Command::Quit),
+            _ => Err(format!(
+                "There is no /{} command. Type /help to see them.",
+                name
+            )),
+        }
+    }
+}
+
+/// Everything the client keeps track of once it has said hello to the server.
+struct Client {
+    name: String,
+    writer: Writer,
+    /// Whether the player asked to watch a game rather than play one.
+    watching: bool,
+    /// Whether the server passes our chat on.
+    chat: bool,
+    prompt: Prompt,
+    /// `None` unless we are playing, including while watching someone else's game.
+    client_color: Option<Color>,
+    /// Red's name, then yellow's.
+    names: [String; 2],
+    game: Game,
+    /// Handed out with the game, and used to take our seat back if the connection drops.
+    token: Option<u64>,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let mut server_ip = String::new();
+    let mut input = stdin_lines();
     println!("What is the server IP?");
-    if let Err(_) = io::stdin().read_line(&mut server_ip) {
+    let Some(mut server_ip) = input.recv().await else {
         println!("Failed to read line");
         return Ok(());
-    }
+    };
     server_ip = server_ip.trim().to_string();
     server_ip.push_str(":60941");
     println!("Connecting to {}", server_ip);
     // Connect to the server
-    let mut stream = TcpStream::connect(server_ip).await?;
-    let mut name = String::new();
-    let mut opponent_name = String::new();
+    let stream = TcpStream::connect(&server_ip).await?;
+    let (reader, mut writer): (Reader, Writer) = codec::split(stream, DEFAULT_MAX_FRAME_SIZE);
+    let mut packets = spawn_reader(reader);
     println!("What is your name?");
-    if let Err(_) = io::stdin().read_line(&mut name) {
-        println!("Failed to read line. Your name is now \"Player\".");
-        name = "Player".to_string();
-    } else {
-        name = name.trim().to_string();
-    }
+    let name = match input.recv().await {
+        Some(name) => name.trim().to_string(),
+        None => {
+            println!("Failed to read line. Your name is now \"Player\".");
+            "Player".to_string()
+        }
+    };
 
-    send_packet(ServerBoundPacket::Init { name: name.clone() }, &mut stream).await?;
+    let mode = loop {
+        println!(
+            "Press enter for a quick match, type create to open a room for someone you know, join to join their room, watch to watch a game, or pick a bot (random, easy, medium, hard)"
+        );
+        let Some(choice) = input.recv().await else {
+            break Mode::QuickMatch;
+        };
+        match choice.trim() {
+            "" | "quick" => break Mode::QuickMatch,
+            "create" => break Mode::CreateRoom,
+            "join" => {
+                println!("{}", ROOM_CODE_PROMPT);
+                match input.recv().await.map(room_code_answer) {
+                    Some(ServerBoundPacket::JoinRoom { code }) => break Mode::JoinRoom(code),
+                    _ => break Mode::QuickMatch,
+                }
+            }
+            "watch" => break Mode::Watch,
+            choice => match choice.parse::<Difficulty>() {
+                Ok(difficulty) => break Mode::Bot(difficulty),
+                Err(e) => println!("{}. Try again.", e),
+            },
+        }
+    };
+
+    writer
+        .send(ServerBoundPacket::Init {
+            name: name.clone(),
+            bot: match mode {
+                Mode::Bot(difficulty) => Some(difficulty),
+                _ => None,
+            },
+            version: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES,
+        })
+        .await?;
+    let watching = matches!(mode, Mode::Watch);
+    let lobby_packet = match mode {
+        Mode::QuickMatch => Some(ServerBoundPacket::Queue),
+        Mode::CreateRoom => Some(ServerBoundPacket::CreateRoom),
+        Mode::JoinRoom(code) => Some(ServerBoundPacket::JoinRoom { code }),
+        // the list is only asked for once the server says it lets anyone watch
+        Mode::Bot(_) | Mode::Watch => None,
+    };
+    if let Some(packet) = lobby_packet {
+        writer.send(packet).await?;
+    }
 
-    let mut client_color = Color::Red;
-    let mut board = Board::new();
+    let mut client = Client {
+        name,
+        writer,
+        watching,
+        chat: false,
+        prompt: Prompt::Column,
+        client_color: None,
+        names: [String::new(), String::new()],
+        game: Game::default(),
+        token: None,
+    };
     loop {
-        // wait for packets and print thenm
-        let packet = read_clientbound_packet(&mut stream).await;
-        println!("read clientbound packet: {:?}", packet);
+        // whichever comes first, so that nothing the server says waits on the player typing
+        let flow = tokio::select! {
+            received = packets.recv() => {
+                let packet = match received.expect("the reader reports why it stopped") {
+                    Ok(Ok(packet)) => packet,
+                    Ok(Err(e)) => {
+                        println!("The server sent a malformed packet: {}", e);
+                        return Err(e.into());
+                    }
+                    Err(e) => {
+                        let Some(token) = client.token else {
+                            println!("Lost connection to the server: {}", e);
+                            return Err(e.into());
+                        };
+                        match reconnect(&server_ip, token).await {
+                            Some((reader, writer)) => {
+                                (packets, client.writer) = (spawn_reader(reader), writer);
+                                continue;
+                            }
+                            None => {
+                                println!("Could not get back into the game.");
+                                return Err(e.into());
+                            }
+                        }
+                    }
+                };
+                println!("read clientbound packet: {:?}", packet);
+                client.handle_packet(packet).await?
+            }
+            Some(line) = input.recv() => client.handle_line(&line).await,
+        };
+        if flow.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl Client {
+    /// Sends `packet`. A failed send is only reported, since the lost connection also shows up
+    /// on the next read, which is where we reconnect.
+    async fn send(&mut self, packet: ServerBoundPacket) {
+        if let Err(e) = self.writer.send(packet).await {
+            println!("Could not send that to the server: {}", e);
+        }
+    }
+
+    /// Shows what the server sent and answers it, breaking once there is nothing left to do.
+    async fn handle_packet(
+        &mut self,
+        packet: ClientBoundPacket,
+    ) -> Result<ControlFlow<()>, Box<dyn Error>> {
         match packet {
+            ClientBoundPacket::Welcome {
+                version,
+                capabilities,
+            } => {
+                println!(
+                    "Connected with protocol version {} (features: {})",
+                    version, capabilities
+                );
+                self.chat = capabilities.contains(Capabilities::CHAT);
+                if self.watching {
+                    if !capabilities.contains(Capabilities::SPECTATE) {
+                        println!("This server does not let anyone watch games.");
+                        return Ok(ControlFlow::Break(()));
+                    }
+                    self.send(ServerBoundPacket::ListGames).await;
+                }
+            }
+            ClientBoundPacket::QueuePosition { position: 0 } => {
+                println!("You left the queue. Type /queue to join it again.");
+            }
+            ClientBoundPacket::QueuePosition { position } => {
+                println!(
+                    "Waiting for an opponent (#{} in the queue, /cancel to leave it)",
+                    position
+                );
+            }
+            ClientBoundPacket::RoomCreated { code } => {
+                println!(
+                    "Your room code is {}. The game starts when your opponent joins with it.",
+                    code
+                );
+            }
+            ClientBoundPacket::GameList { games } => {
+                if games.is_empty() {
+                    println!("Nobody is playing right now.");
+                }
+                for game in games {
+                    println!(
+                        "{}: {} (red) vs {} (yellow)",
+                        game.id, game.red, game.yellow
+                    );
+                }
+                println!(
+                    "Type the number of the game to watch, or press enter to refresh the list"
+                );
+                self.prompt = Prompt::Game;
+            }
+            ClientBoundPacket::Chat { from, text } => println!("{}: {}", from, text),
             ClientBoundPacket::GameStart {
                 opponent,
                 your_color,
+                config,
+                token,
+            } => {
+                self.names[your_color.index()] = self.name.clone();
+                self.names[your_color.opponent().index()] = opponent;
+                self.client_color = Some(your_color);
+                self.token = Some(token);
+                self.game = Game::new(config);
+                self.prompt = Prompt::Column;
+                // the board itself, and the first prompt, come with the `State` that follows
+            }
+            ClientBoundPacket::State {
+                red,
+                yellow,
+                config,
+                cells,
+                to_move: _,
+                move_number,
             } => {
-                opponent_name = opponent;
-                client_color = your_color;
+                let board = match Board::from_cells(config, &cells) {
+                    Ok(board) => board,
+                    Err(e) => {
+                        println!("The server sent a board that cannot be played on: {}", e);
+                        return Err(e.into());
+                    }
+                };
+                self.game = Game::from_board(board);
+                self.names = [red, yellow];
+                self.prompt = Prompt::Column;
                 print!("\x1B[2J\x1B[1;1H");
-                if &client_color == &Color::Red {
-                    println!("Red: {} (you)\nYellow: {}\n{}", &name, opponent_name, board);
-                } else {
-                    println!("Red: {}\nYellow: {} (you)\n{}", opponent_name, &name, board);
-                }
-                if &client_color == &Color::Red {
-                    play(&mut board, &mut stream).await?;
-                }
+                print_game(&self.names, self.client_color, self.game.board());
+                println!("Move {}", move_number + 1);
+                self.prompt_move();
             }
             ClientBoundPacket::Move { col, color } => {
-                board
-                    .play_move(
-                        col,
-                        match color {
-                            Color::Red => 1,
-                            Color::Yellow => 2,
-                        },
-                    )
-                    .unwrap();
-                print!("\x1B[2J\x1B[1;1H");
-                if &client_color == &Color::Red {
-                    println!("Red: {} (you)\nYellow: {}\n{}", &name, opponent_name, board);
-                } else {
-                    println!("Red: {}\nYellow: {} (you)\n{}", opponent_name, &name, board);
-                }
-
-                if color != client_color {
-                    play(&mut board, &mut stream).await?;
+                if let Err(e) = self.game.apply(color, col) {
+                    // out of step with the server, so ask it for the whole board again
+                    println!("The server sent a move that does not fit the game: {}", e);
+                    self.send(ServerBoundPacket::RequestState).await;
+                    return Ok(ControlFlow::Continue(()));
                 }
                 print!("\x1B[2J\x1B[1;1H");
+                print_game(&self.names, self.client_color, self.game.board());
+                self.prompt_move();
             }
-            ClientBoundPacket::GameResult { result, col, color } => {
+            ClientBoundPacket::Error { code, message } => {
+                println!("The server rejected that: {}", message);
+                match code {
+                    ErrorCode::UnknownSession
+                    | ErrorCode::VersionMismatch
+                    | ErrorCode::MissingCapability => {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                    ErrorCode::UnknownRoom => {
+                        println!("{}", ROOM_CODE_PROMPT);
+                        self.prompt = Prompt::RoomCode;
+                    }
+                    // the list was out of date, so fetch it again
+                    ErrorCode::UnknownGame => self.send(ServerBoundPacket::ListGames).await,
+                    // the move we sent was refused, so it is still our turn
+                    ErrorCode::IllegalMove | ErrorCode::NotYourTurn => self.prompt_move(),
+                    _ => {}
+                }
+            }
+            ClientBoundPacket::GameResult {
+                result,
+                col,
+                color,
+                reason,
+                lines,
+            } => {
                 if let Some(col) = col {
-                    board
-                        .play_move(
-                            col,
-                            match color {
-                                Color::Red => 1,
-                                Color::Yellow => 2,
-                            },
-                        )
-                        .unwrap();
+                    if let Err(e) = self.game.apply(color, col) {
+                        println!("The server sent a move that does not fit the game: {}", e);
+                        return Err(e.into());
+                    }
+                    if self.game.result() != result {
+                        println!(
+                            "The server says the game ended {:?}, but the board says {:?}",
+                            result,
+                            self.game.result()
+                        );
+                    }
                 }
-                if &client_color == &Color::Red {
-                    println!("Red: {} (you)\nYellow: {}\n{}", &name, opponent_name, board);
-                } else {
-                    println!("Red: {}\nYellow: {} (you)\n{}", opponent_name, &name, board);
+                let winning_cells = lines.concat();
+                let board = self.game.board().highlight(&winning_cells);
+                print_game(&self.names, self.client_color, board);
+                let loser = &self.names[color.index()];
+                match reason {
+                    EndReason::Completed => {}
+                    EndReason::Forfeit if self.client_color == Some(color) => {
+                        println!("You forfeited.")
+                    }
+                    EndReason::Forfeit => println!("{} forfeited.", loser),
+                    EndReason::Disconnected if self.client_color.is_some() => {
+                        println!("{} disconnected, so you win.", loser)
+                    }
+                    EndReason::Disconnected => println!("{} disconnected.", loser),
                 }
                 println!("Game over! Result: {:?}", result);
-                break;
+                return Ok(ControlFlow::Break(()));
             }
         }
+        Ok(ControlFlow::Continue(()))
     }
-    Ok(())
-}
 
-async fn play(board: &mut Board, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
-    println!("It's your turn! What column do you want to play in? (0-6)");
-    // get user input
+    /// Carries out a line the player typed, breaking if they asked to leave.
+    async fn handle_line(&mut self, line: &str) -> ControlFlow<()> {
+        let command = match Command::parse(line) {
+            Ok(command) => command,
+            Err(e) => {
+                println!("{}", e);
+                return ControlFlow::Continue(());
+            }
+        };
+        let in_game = self.client_color.is_some();
+        let packet = match command {
+            Command::Help => {
+                println!("{}", HELP);
+                None
+            }
+            Command::Quit => return ControlFlow::Break(()),
+            Command::Say(_) if !self.chat => {
+                println!("This server does not pass chat on.");
+                None
+            }
+            Command::Say(text) if text.is_empty() => {
+                println!("Type a message after /say.");
+                None
+            }
+            Command::Say(text) => Some(ServerBoundPacket::Chat { text }),
+            Command::Refresh => Some(ServerBoundPacket::RequestState),
+            Command::Forfeit if in_game => {
+                println!("Forfeiting game");
+                Some(ServerBoundPacket::Forfeit)
+            }
+            Command::Forfeit => {
+                println!("You are not playing a game.");
+                None
+            }
+            Command::Cancel | Command::Queue if in_game => {
+                println!("You are in a game already. Type /forfeit to leave it.");
+                None
+            }
+            Command::Cancel => Some(ServerBoundPacket::CancelQueue),
+            Command::Queue => Some(ServerBoundPacket::Queue),
+            Command::Answer(answer) => match self.prompt {
+                Prompt::Column => self.column_answer(&answer),
+                Prompt::RoomCode => {
+                    self.prompt = Prompt::Column;
+                    Some(room_code_answer(answer))
+                }
+                Prompt::Game => match answer.parse() {
+                    Ok(id) => {
+                        self.prompt = Prompt::Column;
+                        Some(ServerBoundPacket::Watch { id })
+                    }
+                    Err(_) => Some(ServerBoundPacket::ListGames),
+                },
+            },
+        };
+        if let Some(packet) = packet {
+            self.send(packet).await;
+        }
+        ControlFlow::Continue(())
+    }
 
-    let col = loop {
-        let mut buf = String::new();
-        if let Err(_) = io::stdin().read_line(&mut buf) {
-            println!("Failed to read line. Try again.");
-            continue;
+    fn prompt_move(&self) {
+        if self.client_color == Some(self.game.turn()) && !self.game.is_over() {
+            println!(
+                "It's your turn! What column do you want to play in? (0-{})",
+                self.game.config().cols() - 1
+            );
         }
-        if let Ok(col) = buf.trim().parse::<u8>() {
-            if col > 7 {
+    }
+
+    /// Reads `answer` as a column to play in, explaining what is wrong with it if anything.
+    fn column_answer(&self, answer: &str) -> Option<ServerBoundPacket> {
+        let Some(color) = self.client_color else {
+            if !answer.is_empty() {
+                println!("You are not playing a game. Type /help to see what you can do.");
+            }
+            return None;
+        };
+        let Ok(col) = answer.parse::<u8>() else {
+            println!("Type a column number, or /help to see what else you can do.");
+            return None;
+        };
+        match self.game.check(color, col) {
+            Ok(()) => {
+                println!("Playing in column {}", col);
+                Some(ServerBoundPacket::Move { col })
+            }
+            Err(GameError::IllegalMove(MoveError::OutOfRange)) => {
                 println!("Column too high. Try again.");
-                continue;
+                None
             }
-            if board.legal_move(col).is_err() {
+            Err(GameError::IllegalMove(MoveError::ColumnFull)) => {
                 println!("Column is full. Try again.");
-                continue;
-            }
-            println!("Playing in column {}", col);
-            break col;
-        } else if buf.trim() == "forfeit" {
-            println!("Forfeiting game");
-            send_packet(ServerBoundPacket::Forfeit, stream).await?;
-            return Ok(());
+                None
+            }
+            Err(e) => {
+                println!("You can't play right now: {}", e);
+                None
+            }
         }
-    };
-    send_packet(ServerBoundPacket::Move { col }, stream).await?;
-    Ok(())
+    }
 }
 
-async fn read_clientbound_packet(stream: &mut TcpStream) -> ClientBoundPacket {
-    let len = match stream.read_u8().await {
-        Ok(len) => len,
-        Err(_) => {
-            panic!("Failed to read length of packet. This could mean the client disconnected.");
+/// Joins the room with the code typed in `answer`, or the queue if nothing was.
+fn room_code_answer(answer: String) -> ServerBoundPacket {
+    match answer.trim() {
+        "" => ServerBoundPacket::Queue,
+        code => ServerBoundPacket::JoinRoom {
+            code: code.to_string(),
+        },
+    }
+}
+
+/// Prints the board under the players' names, marking which one is us unless we are watching.
+fn print_game(names: &[String; 2], client_color: Option<Color>, board: impl Display) {
+    let you = |color| {
+        if client_color == Some(color) {
+            " (you)"
+        } else {
+            ""
         }
     };
-    let mut buf = vec![0; len as usize];
-    stream.read_exact(&mut buf).await.unwrap();
-    ClientBoundPacket::deserialize(&buf)
+    println!(
+        "Red: {}{}\nYellow: {}{}\n{}",
+        names[Color::Red.index()],
+        you(Color::Red),
+        names[Color::Yellow.index()],
+        you(Color::Yellow),
+        board
+    );
+}
+
+/// Reads stdin on a thread of its own and hands over each line, so that waiting for one never
+/// blocks the runtime and can be given up for a packet that arrives first.
+fn stdin_lines() -> Input {
+    let (lines, input) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if lines.send(line).is_err() {
+                return;
+            }
+        }
+    });
+    input
+}
+
+/// Reads packets on a task of its own, since a read given up halfway through a frame would lose
+/// the rest of it.
+fn spawn_reader(mut reader: Reader) -> Packets {
+    let (received, packets) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let packet = reader.read().await;
+            let failed = packet.is_err();
+            if received.send(packet).is_err() || failed {
+                return;
+            }
+        }
+    });
+    packets
+}
+
+/// Connects again and asks for the seat `token` belongs to. The server answers on the new
+/// stream with a `State`, or an `Error` if the game is gone.
+async fn reconnect(server_ip: &str, token: u64) -> Option<(Reader, Writer)> {
+    for attempt in 1..=RECONNECT_ATTEMPTS {
+        println!(
+            "Lost connection to the server, reconnecting ({}/{})",
+            attempt, RECONNECT_ATTEMPTS
+        );
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let Ok(stream) = TcpStream::connect(server_ip).await else {
+            continue;
+        };
+        let (reader, mut writer) = codec::split(stream, DEFAULT_MAX_FRAME_SIZE);
+        if writer
+            .send(ServerBoundPacket::Resume { token })
+            .await
+            .is_ok()
+        {
+            return Some((reader, writer));
+        }
+    }
+    None
+}
+
+#[test]
+fn test_parse_command() {
+    assert_eq!(
+        Command::parse("/say good luck "),
+        Ok(Command::Say("good luck".to_string()))
+    );
+    assert_eq!(Command::parse("/say"), Ok(Command::Say(String::new())));
+    assert_eq!(Command::parse(" forfeit"), Ok(Command::Forfeit));
+    assert_eq!(Command::parse("/forfeit"), Ok(Command::Forfeit));
+    assert_eq!(Command::parse("refresh"), Ok(Command::Refresh));
+    assert_eq!(Command::parse("/cancel"), Ok(Command::Cancel));
+    assert_eq!(Command::parse("3\n"), Ok(Command::Answer("3".to_string())));
+    assert_eq!(Command::parse(""), Ok(Command::Answer(String::new())));
+    assert!(Command::parse("/dance").is_err());
 }
@@ -1,213 +1,1266 @@
-use connect_4::send_packet;
-use connect_4::Board;
+use connect_4::bot::Bot;
+use connect_4::codec;
+use connect_4::game::Game;
+use connect_4::BoardConfig;
+use connect_4::Capabilities;
 use connect_4::ClientBoundPacket;
 use connect_4::Color;
 use connect_4::Deserialize;
+use connect_4::EndReason;
+use connect_4::ErrorCode;
 use connect_4::GameResult;
+use connect_4::GameSummary;
+use connect_4::ProtocolError;
+use connect_4::Serialize;
 use connect_4::ServerBoundPacket;
+use connect_4::{DEFAULT_MAX_FRAME_SIZE, MAX_CHAT_LEN, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use futures_util::{FutureExt, SinkExt, StreamExt};
 use rand::Rng;
-use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, error::SendError};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration, Instant};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
 
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::error::Error;
+use std::future::Future;
 use std::io;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
 
-// async fn accept_connection(mut stream: TcpStream, state: Arc<Mutex<i32>>) {
-//     loop {
-//         let packet = read_serverbound_packet(&mut stream).await;
-//         println!("read serverbound packet: {:?}", packet);
-//         let mut state = state.lock().await;
-//         *state += 1;
-//         println!("total packets read: {}", state);
-//     }
-// }
-
-async fn play_game(stream1: TcpStream, stream2: TcpStream) {
+/// The optional features this server offers to clients that ask for them.
+const CAPABILITIES: Capabilities = Capabilities::VARIANTS
+    .union(Capabilities::SPECTATE)
+    .union(Capabilities::CHAT);
+
+/// How many chat messages a player may send within `CHAT_WINDOW` before the rest are refused.
+const CHAT_BURST: usize = 5;
+const CHAT_WINDOW: Duration = Duration::from_secs(10);
+
+/// The first protocol version with `QueuePosition`, which older clients cannot decode.
+const QUEUE_VERSION: u8 = 3;
+
+/// The first protocol version whose clients choose between the queue and a room themselves,
+/// instead of being queued by their `Init`.
+const ROOMS_VERSION: u8 = 4;
+
+/// What join codes are made of, leaving out characters that are easy to mix up, like 0 and O.
+const ROOM_CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const ROOM_CODE_LEN: usize = 6;
+
+/// Server-wide options, read once from the command line.
+#[derive(Clone, Copy, Debug)]
+struct Settings {
+    config: BoardConfig,
+    /// How many rejected packets a player may send in one game before they forfeit it.
+    max_violations: u32,
+    /// How long a disconnected player's seat is held for them to resume.
+    grace_period: Duration,
+    /// The longest packet a client may send, in bytes, or the longest line for JSON clients. A
+    /// client that sends a longer one is disconnected.
+    max_frame_size: u32,
+    /// Where clients speaking the binary protocol connect.
+    port: u16,
+    /// Where clients speaking newline-delimited JSON connect.
+    json_port: u16,
+    /// Where browsers connect over WebSocket.
+    ws_port: u16,
+    /// Whether spectators who can read chat are sent the players' messages.
+    spectator_chat: bool,
+}
+
+/// Running games by the session tokens they handed out, so that a `Resume` can find its seat.
+type Sessions = Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<(u64, Connection)>>>>;
+
+/// A running game as the lobby sees it: who is playing, and where to send spectators.
+#[derive(Clone)]
+struct LiveGame {
+    red: String,
+    yellow: String,
+    spectators: mpsc::UnboundedSender<Spectator>,
+}
+
+/// Someone watching a game, who cannot play in it.
+struct Spectator {
+    connection: Connection,
+    /// The capabilities agreed on in their handshake.
+    capabilities: Capabilities,
+}
+
+/// Running games by the id spectators pick them with.
+type Games = Arc<Mutex<HashMap<u32, LiveGame>>>;
+
+/// How a listener's clients encode their packets.
+#[derive(Clone, Copy, Debug)]
+enum Protocol {
+    /// Length-prefixed frames, as the bundled client speaks.
+    Binary,
+    /// One JSON object per line, for scripts and web tools.
+    Json,
+    /// One packet per binary WebSocket message, laid out as in `Binary` but without the length
+    /// prefix, for browsers.
+    WebSocket,
+}
+
+/// A client's socket, with reading and writing moved onto tasks of their own so that a game can
+/// listen to both players at once and never cares which protocol either of them speaks.
+struct Connection {
+    packets: mpsc::UnboundedReceiver<Result<ServerBoundPacket, ProtocolError>>,
+    outgoing: mpsc::UnboundedSender<ClientBoundPacket>,
+    /// Stopped when the connection is dropped, since it would otherwise wait on the socket for as
+    /// long as the peer keeps it open.
+    reader: JoinHandle<()>,
+}
+
+impl Connection {
+    /// Sets up a freshly accepted socket, which for WebSockets includes the HTTP upgrade. Binary
+    /// clients still framing packets the way version 1 did are told to update and refused.
+    async fn accept(
+        stream: TcpStream,
+        protocol: Protocol,
+        max_frame_size: u32,
+    ) -> Result<Self, tokio_tungstenite::tungstenite::Error> {
+        if let Protocol::Binary = protocol {
+            let mut first = [0];
+            if stream.peek(&mut first).await? > 0
+                && codec::is_legacy_frame(first[0], max_frame_size)
+            {
+                refuse_legacy(stream).await;
+                let e = io::Error::new(io::ErrorKind::InvalidData, "client speaks version 1");
+                return Err(e.into());
+            }
+        }
+        let (incoming, packets) = mpsc::unbounded_channel();
+        let (outgoing, mut queued) = mpsc::unbounded_channel::<ClientBoundPacket>();
+        // both tasks stop at the first error. The reader closing `packets` is how the game hears
+        // about a disconnect, and the writer finishes sending what was queued before it closes
+        // the socket
+        let reader = match protocol {
+            Protocol::Binary => {
+                let (mut reader, mut writer) = codec::split(stream, max_frame_size);
+                tokio::spawn(async move {
+                    while let Some(packet) = queued.recv().await {
+                        if writer.send(packet).await.is_err() {
+                            return;
+                        }
+                    }
+                    writer.shutdown().await.ok();
+                });
+                tokio::spawn(async move {
+                    while let Ok(packet) = reader.read().await {
+                        if incoming.send(packet).is_err() {
+                            return;
+                        }
+                    }
+                })
+            }
+            Protocol::Json => {
+                let (mut reader, mut writer) = codec::split_json(stream, max_frame_size);
+                tokio::spawn(async move {
+                    while let Some(packet) = queued.recv().await {
+                        if writer.send(packet).await.is_err() {
+                            return;
+                        }
+                    }
+                    writer.shutdown().await.ok();
+                });
+                tokio::spawn(async move {
+                    while let Ok(packet) = reader.read().await {
+                        if incoming.send(packet).is_err() {
+                            return;
+                        }
+                    }
+                })
+            }
+            Protocol::WebSocket => {
+                let config = WebSocketConfig {
+                    max_message_size: Some(max_frame_size as usize),
+                    max_frame_size: Some(max_frame_size as usize),
+                    ..Default::default()
+                };
+                let socket =
+                    tokio_tungstenite::accept_async_with_config(stream, Some(config)).await?;
+                let (mut writer, mut reader) = socket.split();
+                tokio::spawn(async move {
+                    while let Some(packet) = queued.recv().await {
+                        println!("sending packet: {:?}", packet);
+                        let message = Message::Binary(packet.serialize());
+                        if writer.send(message).await.is_err() {
+                            return;
+                        }
+                    }
+                    writer.close().await.ok();
+                });
+                tokio::spawn(async move {
+                    while let Some(Ok(message)) = reader.next().await {
+                        let packet = match message {
+                            Message::Binary(buf) => ServerBoundPacket::deserialize(&buf),
+                            Message::Close(_) => return,
+                            // pings are answered by tungstenite, and text is not part of the protocol
+                            message => {
+                                println!("ignoring websocket message {:?}", message);
+                                continue;
+                            }
+                        };
+                        if incoming.send(packet).is_err() {
+                            return;
+                        }
+                    }
+                })
+            }
+        };
+        Ok(Connection {
+            packets,
+            outgoing,
+            reader,
+        })
+    }
+
+    /// Queues `packet` to be written. Fails once the connection is known to be gone.
+    fn send(&self, packet: ClientBoundPacket) -> io::Result<()> {
+        self.outgoing
+            .send(packet)
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // the writer stops by itself once `outgoing` is gone and it has sent everything queued
+        self.reader.abort();
+    }
+}
+
+/// One side of a game: either a client, who may have dropped and be due back, or a bot the
+/// server plays for.
+enum Player {
+    Human {
+        name: String,
+        connection: Option<Connection>,
+        /// The capabilities agreed on in their handshake, which still hold after a `Resume`.
+        capabilities: Capabilities,
+    },
+    Bot {
+        bot: Bot,
+        name: String,
+    },
+}
+
+impl Player {
+    /// Wraps a connection that has finished its handshake.
+    fn human(connection: Connection, name: String, capabilities: Capabilities) -> Self {
+        Player::Human {
+            name,
+            connection: Some(connection),
+            capabilities,
+        }
+    }
+
+    /// What the player's client can handle. Bots take part in nothing optional.
+    fn capabilities(&self) -> Capabilities {
+        match self {
+            Player::Human { capabilities, .. } => *capabilities,
+            Player::Bot { .. } => Capabilities::NONE,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Player::Human { name, .. } | Player::Bot { name, .. } => name,
+        }
+    }
+
+    /// Forgets a connection that has gone away. The seat stays taken until `reconnect`.
+    fn disconnect(&mut self) {
+        if let Player::Human { connection, .. } = self {
+            *connection = None;
+        }
+    }
+
+    /// Seats a resumed connection, replacing the old one if the server had not noticed it drop.
+    fn reconnect(&mut self, resumed: Connection) {
+        if let Player::Human { connection, .. } = self {
+            *connection = Some(resumed);
+        }
+    }
+
+    /// Sends `packet` if the player is there to receive it.
+    fn send(&self, packet: ClientBoundPacket) -> io::Result<()> {
+        match self {
+            Player::Human {
+                connection: Some(connection),
+                ..
+            } => connection.send(packet),
+            Player::Human { .. } | Player::Bot { .. } => Ok(()),
+        }
+    }
+
+    /// Waits for the next packet the player sends, or `None` once their connection is gone.
+    /// Bots never send any, since their moves come from `search`.
+    async fn next_packet(&mut self) -> Option<Result<ServerBoundPacket, ProtocolError>> {
+        match self {
+            Player::Human {
+                connection: Some(connection),
+                ..
+            } => connection.packets.recv().await,
+            // a disconnected player is quiet until they resume
+            Player::Human { .. } | Player::Bot { .. } => std::future::pending().await,
+        }
+    }
+
+    /// Starts working out the move for `color` if this is a bot and it is their turn.
+    fn search(&self, game: &Game, color: Color) -> Option<JoinHandle<u8>> {
+        let Player::Bot { bot, .. } = self else {
+            return None;
+        };
+        if game.turn() != color || game.is_over() {
+            return None;
+        }
+        let (bot, board) = (*bot, game.board().clone());
+        // a deep search can take a while, so keep it off the async workers
+        Some(tokio::task::spawn_blocking(move || {
+            bot.choose_move(&board, color)
+                .expect("bot was asked to move on a full board")
+        }))
+    }
+}
+
+/// Something that wakes a running game up.
+enum Event {
+    Packet(Color, Option<Result<ServerBoundPacket, ProtocolError>>),
+    Resume(u64, Connection),
+    /// A disconnected player's grace period ran out.
+    Expired(Color),
+    /// Someone in the lobby asked to watch.
+    Watch(Spectator),
+    /// A packet from the spectator at this index, or `None` once they are gone.
+    Spectator(usize, Option<Result<ServerBoundPacket, ProtocolError>>),
+}
+
+async fn play_game(
+    player1: Player,
+    player2: Player,
+    settings: Settings,
+    sessions: Sessions,
+    games: Games,
+) {
     // choose player to go first randomly
     let rng = rand::thread_rng().gen_bool(0.5);
-    let (mut red_player, mut yellow_player) = if rng {
-        (stream1, stream2)
+    let mut players = if rng {
+        [player1, player2]
     } else {
-        (stream2, stream1)
+        [player2, player1]
     };
 
-    let mut board = Board::new();
-    let mut turn = Color::Red;
-    let name1 = match read_serverbound_packet(&mut red_player).await {
-        ServerBoundPacket::Init { name } => name,
-        _ => panic!("Expected init packet"),
-    };
-    let name2 = match read_serverbound_packet(&mut yellow_player).await {
-        ServerBoundPacket::Init { name } => name,
-        _ => panic!("Expected init packet"),
+    let mut game = Game::new(settings.config);
+    let tokens: [u64; 2] = rand::random();
+    let mut violations = [0; 2];
+    // when each player's recent chat messages were sent, for rate limiting
+    let mut chats: [VecDeque<Instant>; 2] = Default::default();
+    // when each disconnected player's seat is given up
+    let mut deadlines: [Option<Instant>; 2] = [None; 2];
+
+    let (resume_tx, mut resumes) = mpsc::unbounded_channel();
+    {
+        let mut sessions = sessions.lock().unwrap();
+        for token in tokens {
+            sessions.insert(token, resume_tx.clone());
+        }
+    }
+    let (spectators_tx, mut watchers) = mpsc::unbounded_channel();
+    let mut spectators = Vec::new();
+    let id = {
+        let mut games = games.lock().unwrap();
+        // the smallest free id, to keep them short enough to type
+        let id = (1..).find(|id| !games.contains_key(id)).unwrap();
+        let game = LiveGame {
+            red: players[Color::Red.index()].name().to_string(),
+            yellow: players[Color::Yellow.index()].name().to_string(),
+            spectators: spectators_tx,
+        };
+        games.insert(id, game);
+        id
     };
 
-    // send startgame packet to each client
-    send_packet(
-        ClientBoundPacket::GameStart {
-            opponent: name2.clone(),
-            your_color: Color::Red,
-        },
-        &mut red_player,
-    )
-    .await
-    .unwrap();
-    send_packet(
-        ClientBoundPacket::GameStart {
-            opponent: name1.clone(),
-            your_color: Color::Yellow,
-        },
-        &mut yellow_player,
-    )
-    .await
-    .unwrap();
+    // send startgame packet to each client. Someone who left just before being paired is caught by
+    // the first read
+    for color in [Color::Red, Color::Yellow] {
+        let packet = ClientBoundPacket::GameStart {
+            opponent: players[color.opponent().index()].name().to_string(),
+            your_color: color,
+            config: settings.config,
+            token: tokens[color.index()],
+        };
+        players[color.index()].send(packet).ok();
+    }
+    broadcast(state(&game, &players), &players, &spectators);
+    // the bot's move being worked out, kept across events so that nothing else restarts it
+    let mut search: Option<(Color, JoinHandle<u8>)> = None;
     loop {
-        // wait for turn
-        match turn {
-            Color::Red => {
-                // read move packet
-                let packet = read_serverbound_packet(&mut red_player).await;
-                println!("read serverbound packet from red: {:?}", packet);
-                match packet {
-                    ServerBoundPacket::Move { col } => {
-                        board.play_move(col, 1).unwrap();
-                        match board.score() {
-                            GameResult::InProgress => {
-                                send_packet(
-                                    ClientBoundPacket::Move {
-                                        col,
-                                        color: Color::Red,
-                                    },
-                                    &mut red_player,
-                                )
-                                .await
-                                .unwrap();
-                                send_packet(
-                                    ClientBoundPacket::Move {
-                                        col,
-                                        color: Color::Red,
-                                    },
-                                    &mut yellow_player,
-                                )
-                                .await
-                                .unwrap();
-                                turn = Color::Yellow
-                            }
-                            result => {
-                                send_packet(
-                                    ClientBoundPacket::GameResult {
-                                        result: result.clone(),
-                                        col: Some(col),
-                                        color: Color::Red,
-                                    },
-                                    &mut red_player,
-                                )
-                                .await
-                                .unwrap();
-                                send_packet(
-                                    ClientBoundPacket::GameResult {
-                                        result,
-                                        col: Some(col),
-                                        color: Color::Red,
-                                    },
-                                    &mut yellow_player,
-                                )
-                                .await
-                                .unwrap();
-                                break;
-                            }
-                        }
-                    }
-                    _ => {
-                        panic!("Expected move packet")
+        if search.is_none() {
+            search = [Color::Red, Color::Yellow].into_iter().find_map(|color| {
+                let handle = players[color.index()].search(&game, color)?;
+                Some((color, handle))
+            });
+        }
+        let expiry = [Color::Red, Color::Yellow]
+            .into_iter()
+            .filter_map(|color| deadlines[color.index()].map(|deadline| (deadline, color)))
+            .min_by_key(|&(deadline, _)| deadline);
+        // listen to both players, so that out-of-turn packets are answered straight away
+        let event = {
+            let [red_player, yellow_player] = &mut players;
+            tokio::select! {
+                packet = red_player.next_packet() => Event::Packet(Color::Red, packet),
+                packet = yellow_player.next_packet() => Event::Packet(Color::Yellow, packet),
+                (color, col) = bot_move(&mut search) => {
+                    Event::Packet(color, Some(Ok(ServerBoundPacket::Move { col })))
+                }
+                Some((token, connection)) = resumes.recv() => Event::Resume(token, connection),
+                Some(connection) = watchers.recv() => Event::Watch(connection),
+                (index, packet) = next_spectator_packet(&mut spectators) => {
+                    Event::Spectator(index, packet)
+                }
+                _ = time::sleep_until(expiry.map_or_else(Instant::now, |(deadline, _)| deadline)),
+                    if expiry.is_some() => Event::Expired(expiry.unwrap().1),
+            }
+        };
+        let (color, packet) = match event {
+            Event::Packet(color, Some(packet)) => (color, packet),
+            Event::Packet(color, None) => {
+                println!(
+                    "{:?} disconnected, holding their seat for {:?}",
+                    color, settings.grace_period
+                );
+                players[color.index()].disconnect();
+                deadlines[color.index()] = Some(Instant::now() + settings.grace_period);
+                continue;
+            }
+            Event::Resume(token, connection) => {
+                let color = if token == tokens[Color::Red.index()] {
+                    Color::Red
+                } else {
+                    Color::Yellow
+                };
+                println!("{:?} resumed their game", color);
+                deadlines[color.index()] = None;
+                let state = state(&game, &players);
+                let player = &mut players[color.index()];
+                player.reconnect(connection);
+                player.send(state).ok();
+                continue;
+            }
+            Event::Expired(color) => {
+                println!("{:?} did not come back in time, they lose", color);
+                concede(
+                    &mut game,
+                    color,
+                    EndReason::Disconnected,
+                    &players,
+                    &spectators,
+                );
+                break;
+            }
+            Event::Watch(spectator) => {
+                println!("someone is watching game {}", id);
+                spectator.connection.send(state(&game, &players)).ok();
+                spectators.push(spectator);
+                continue;
+            }
+            Event::Spectator(index, None) => {
+                spectators.remove(index);
+                continue;
+            }
+            Event::Spectator(index, Some(packet)) => {
+                let packet = match packet {
+                    Ok(ServerBoundPacket::RequestState) => state(&game, &players),
+                    Ok(packet) => ClientBoundPacket::Error {
+                        code: ErrorCode::UnexpectedPacket,
+                        message: format!("spectators cannot send {:?}", packet),
+                    },
+                    Err(e) => ClientBoundPacket::Error {
+                        code: ErrorCode::MalformedPacket,
+                        message: e.to_string(),
+                    },
+                };
+                spectators[index].connection.send(packet).ok();
+                continue;
+            }
+        };
+        println!("read serverbound packet from {:?}: {:?}", color, packet);
+        let (code, message) = match packet {
+            Ok(ServerBoundPacket::Move { col }) => match game.apply(color, col) {
+                Ok(result) => {
+                    let (packet, game_over) = match result {
+                        GameResult::InProgress => (ClientBoundPacket::Move { col, color }, false),
+                        result => (
+                            ClientBoundPacket::GameResult {
+                                result,
+                                col: Some(col),
+                                color,
+                                reason: EndReason::Completed,
+                                lines: game.board().winning_lines(),
+                            },
+                            true,
+                        ),
+                    };
+                    broadcast(packet, &players, &spectators);
+                    if game_over {
+                        break;
                     }
+                    continue;
                 }
+                Err(e) => (e.code(), format!("column {}: {}", col, e)),
+            },
+            Ok(ServerBoundPacket::RequestState) => {
+                let state = state(&game, &players);
+                players[color.index()].send(state).ok();
+                continue;
             }
-            Color::Yellow => {
-                // read move packet
-                let packet = read_serverbound_packet(&mut yellow_player).await;
-                println!("read serverbound packet from yellow: {:?}", packet);
-                match packet {
-                    ServerBoundPacket::Move { col } => {
-                        board.play_move(col, 2).unwrap();
-                        match board.score() {
-                            GameResult::InProgress => {
-                                send_packet(
-                                    ClientBoundPacket::Move {
-                                        col,
-                                        color: Color::Yellow,
-                                    },
-                                    &mut red_player,
-                                )
-                                .await
-                                .unwrap();
-                                send_packet(
-                                    ClientBoundPacket::Move {
-                                        col,
-                                        color: Color::Yellow,
-                                    },
-                                    &mut yellow_player,
-                                )
-                                .await
-                                .unwrap();
-                                turn = Color::Red
-                            }
-                            result => {
-                                send_packet(
-                                    ClientBoundPacket::GameResult {
-                                        result: result.clone(),
-                                        col: Some(col),
-                                        color: Color::Yellow,
-                                    },
-                                    &mut red_player,
-                                )
-                                .await
-                                .unwrap();
-                                send_packet(
-                                    ClientBoundPacket::GameResult {
-                                        result,
-                                        col: Some(col),
-                                        color: Color::Yellow,
-                                    },
-                                    &mut yellow_player,
-                                )
-                                .await
-                                .unwrap();
-                                break;
-                            }
-                        }
+            Ok(ServerBoundPacket::Forfeit) => {
+                println!("{:?} forfeits", color);
+                concede(&mut game, color, EndReason::Forfeit, &players, &spectators);
+                break;
+            }
+            Ok(ServerBoundPacket::Chat { text })
+                if players[color.index()]
+                    .capabilities()
+                    .contains(Capabilities::CHAT) =>
+            {
+                // refused chat is not held against the player like a bad move is
+                if let Err(message) = check_chat(&text, &mut chats[color.index()]) {
+                    let code = ErrorCode::ChatRejected;
+                    players[color.index()]
+                        .send(ClientBoundPacket::Error { code, message })
+                        .ok();
+                    continue;
+                }
+                let packet = ClientBoundPacket::Chat {
+                    from: players[color.index()].name().to_string(),
+                    text,
+                };
+                let listeners = if settings.spectator_chat {
+                    &spectators[..]
+                } else {
+                    &[]
+                };
+                relay_chat(packet, &players, listeners);
+                continue;
+            }
+            Ok(packet) => (
+                ErrorCode::UnexpectedPacket,
+                format!("{:?} is not allowed during a game", packet),
+            ),
+            Err(e) => (ErrorCode::MalformedPacket, e.to_string()),
+        };
+
+        // a failed write shows up as a disconnect on the next read
+        players[color.index()]
+            .send(ClientBoundPacket::Error { code, message })
+            .ok();
+        let count = &mut violations[color.index()];
+        *count += 1;
+        if *count > settings.max_violations {
+            println!("{:?} sent too many bad packets, they forfeit", color);
+            concede(&mut game, color, EndReason::Forfeit, &players, &spectators);
+            break;
+        }
+    }
+
+    games.lock().unwrap().remove(&id);
+    let mut sessions = sessions.lock().unwrap();
+    for token in tokens {
+        sessions.remove(&token);
+    }
+}
+
+/// Waits for the move being worked out in `search` and clears it, along with whose move it is.
+/// Never finishes while there is none. Waiting again after being interrupted carries on with the
+/// same search.
+async fn bot_move(search: &mut Option<(Color, JoinHandle<u8>)>) -> (Color, u8) {
+    let Some((color, handle)) = search else {
+        return std::future::pending().await;
+    };
+    let col = handle.await.expect("bot search panicked");
+    let color = *color;
+    *search = None;
+    (color, col)
+}
+
+/// Waits for the next packet from any of `spectators`, or `None` once their connection is gone,
+/// along with which of them it came from. Never finishes while there are none.
+async fn next_spectator_packet(
+    spectators: &mut [Spectator],
+) -> (usize, Option<Result<ServerBoundPacket, ProtocolError>>) {
+    std::future::poll_fn(|cx| {
+        for (index, spectator) in spectators.iter_mut().enumerate() {
+            if let Poll::Ready(packet) = spectator.connection.packets.poll_recv(cx) {
+                return Poll::Ready((index, packet));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Describes the whole of `game`, for players that join, resume or ask for it.
+fn state(game: &Game, players: &[Player; 2]) -> ClientBoundPacket {
+    ClientBoundPacket::State {
+        red: players[Color::Red.index()].name().to_string(),
+        yellow: players[Color::Yellow.index()].name().to_string(),
+        config: game.config(),
+        cells: game.board().cells(),
+        to_move: game.turn(),
+        move_number: game.moves().len() as u8,
+    }
+}
+
+/// Ends `game` with `color` losing for `reason` and tells everyone who won.
+fn concede(
+    game: &mut Game,
+    color: Color,
+    reason: EndReason,
+    players: &[Player; 2],
+    spectators: &[Spectator],
+) {
+    let result = match reason {
+        EndReason::Disconnected => game.abandon(color),
+        _ => game.forfeit(color),
+    }
+    .expect("a game that is over has no one left to concede");
+    let packet = ClientBoundPacket::GameResult {
+        result,
+        col: None,
+        color,
+        reason,
+        lines: Vec::new(),
+    };
+    broadcast(packet, players, spectators);
+}
+
+/// Sends `packet` to both players and every spectator. Anyone whose connection is gone simply
+/// misses it, since the game notices the disconnect on its next read.
+fn broadcast(packet: ClientBoundPacket, players: &[Player; 2], spectators: &[Spectator]) {
+    for player in players {
+        player.send(packet.clone()).ok();
+    }
+    for spectator in spectators {
+        spectator.connection.send(packet.clone()).ok();
+    }
+}
+
+/// Sends a chat `packet` to everyone in `players` and `spectators` whose client can show it.
+fn relay_chat(packet: ClientBoundPacket, players: &[Player; 2], spectators: &[Spectator]) {
+    for player in players {
+        if player.capabilities().contains(Capabilities::CHAT) {
+            player.send(packet.clone()).ok();
+        }
+    }
+    for spectator in spectators {
+        if spectator.capabilities.contains(Capabilities::CHAT) {
+            spectator.connection.send(packet.clone()).ok();
+        }
+    }
+}
+
+/// Decides whether a player may say `text`, given when they sent their `recent` messages, and
+/// counts it against them if so. The reason is sent back to the player otherwise.
+fn check_chat(text: &str, recent: &mut VecDeque<Instant>) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Err("there is nothing to send".to_string());
+    }
+    if text.len() > MAX_CHAT_LEN {
+        return Err(format!(
+            "messages can be at most {} bytes long",
+            MAX_CHAT_LEN
+        ));
+    }
+    // they end up on other people's terminals, where escape sequences could do anything
+    if text.chars().any(char::is_control) {
+        return Err("messages cannot contain control characters".to_string());
+    }
+    let now = Instant::now();
+    while recent
+        .front()
+        .is_some_and(|&sent| now - sent >= CHAT_WINDOW)
+    {
+        recent.pop_front();
+    }
+    if recent.len() >= CHAT_BURST {
+        return Err(format!(
+            "slow down, you can send {} messages every {} seconds",
+            CHAT_BURST,
+            CHAT_WINDOW.as_secs()
+        ));
+    }
+    recent.push_back(now);
+    Ok(())
+}
+
+/// Reads the first packet of a new connection. An `Init` either starts a game against a bot or
+/// hands the player over to be paired with the next human, and a `Resume` goes back to its game.
+async fn handshake(
+    mut connection: Connection,
+    settings: Settings,
+    sessions: Sessions,
+    games: Games,
+    lobby: mpsc::UnboundedSender<Waiting>,
+) {
+    let (name, bot, version, capabilities) = match connection.packets.recv().await {
+        // the binary decoder refuses other versions itself, but JSON has no such check
+        Some(Ok(ServerBoundPacket::Init { version, .. }))
+            if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) =>
+        {
+            println!(
+                "dropping connection, unsupported protocol version {}",
+                version
+            );
+            connection.send(version_mismatch(Some(version))).ok();
+            return;
+        }
+        Some(Ok(ServerBoundPacket::Init {
+            name,
+            bot,
+            version,
+            capabilities,
+        })) => {
+            let capabilities = capabilities.intersection(CAPABILITIES);
+            let config = settings.config;
+            if config != BoardConfig::STANDARD && !capabilities.contains(Capabilities::VARIANTS) {
+                println!("dropping connection, client cannot play on a variant board");
+                let packet = ClientBoundPacket::Error {
+                    code: ErrorCode::MissingCapability,
+                    message: format!(
+                        "this server plays connect {} on a {}x{} board, which your client does not support",
+                        config.connect(),
+                        config.rows(),
+                        config.cols()
+                    ),
+                };
+                connection.send(packet).ok();
+                return;
+            }
+            let welcome = ClientBoundPacket::Welcome {
+                version,
+                capabilities,
+            };
+            if connection.send(welcome).is_err() {
+                println!("connection closed during handshake");
+                return;
+            }
+            (name, bot, version, capabilities)
+        }
+        Some(Ok(ServerBoundPacket::Resume { token })) => {
+            let game = sessions.lock().unwrap().get(&token).cloned();
+            match game {
+                Some(game) => {
+                    game.send((token, connection)).ok();
+                }
+                None => {
+                    println!("dropping connection, nothing to resume for its token");
+                    let packet = ClientBoundPacket::Error {
+                        code: ErrorCode::UnknownSession,
+                        message: "that game is over or never existed".to_string(),
+                    };
+                    connection.send(packet).ok();
+                }
+            }
+            return;
+        }
+        Some(Ok(packet)) => {
+            println!("dropping connection, expected init but got {:?}", packet);
+            return;
+        }
+        Some(Err(e @ (ProtocolError::BadMagic | ProtocolError::UnsupportedVersion(_)))) => {
+            println!("dropping connection, {}", e);
+            let version = match e {
+                ProtocolError::UnsupportedVersion(version) => Some(version),
+                _ => None,
+            };
+            connection.send(version_mismatch(version)).ok();
+            return;
+        }
+        Some(Err(e)) => {
+            println!("dropping connection, malformed init packet: {}", e);
+            return;
+        }
+        None => {
+            println!("connection closed before init");
+            return;
+        }
+    };
+    match bot {
+        Some(difficulty) => {
+            println!("starting game against a {} bot", difficulty);
+            let bot = Player::Bot {
+                bot: Bot::new(difficulty),
+                name: format!("Bot ({})", difficulty),
+            };
+            let player = Player::human(connection, name, capabilities);
+            play_game(player, bot, settings, sessions, games).await;
+        }
+        None => {
+            println!("{} joined the lobby", name);
+            let waiting = Waiting {
+                name,
+                connection,
+                version,
+                capabilities,
+                position: 0,
+            };
+            lobby.send(waiting).ok();
+        }
+    }
+}
+
+/// The refusal for a client speaking `version`, or `None` for one from before versioning.
+fn version_mismatch(version: Option<u8>) -> ClientBoundPacket {
+    let (code, version, advice) = match version {
+        Some(version) if version > PROTOCOL_VERSION => (
+            ErrorCode::VersionMismatch,
+            format!("version {}", version),
+            "Please use an older client until the server is updated",
+        ),
+        Some(version) => (
+            ErrorCode::VersionMismatch,
+            format!("version {}", version),
+            "Please update your client",
+        ),
+        // clients from before versioning do not know the newer code, but can still print the
+        // message
+        None => (
+            ErrorCode::MalformedPacket,
+            "an older version".to_string(),
+            "Please update your client",
+        ),
+    };
+    ClientBoundPacket::Error {
+        code,
+        message: format!(
+            "this server speaks protocol versions {} to {}, but your client speaks {}. {}",
+            MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, version, advice
+        ),
+    }
+}
+
+/// Tells a version 1 client to update, in the framing it reads, and hangs up.
+async fn refuse_legacy(mut stream: TcpStream) {
+    // their `Init` is read first, since closing the socket with it unread resets the connection
+    // and can lose the reply
+    if codec::read_legacy_frame(&mut stream).await.is_ok() {
+        codec::send_legacy_packet(version_mismatch(None), &mut stream)
+            .await
+            .ok();
+    }
+    stream.shutdown().await.ok();
+}
+
+/// A player who finished their handshake and is not in a game.
+struct Waiting {
+    name: String,
+    connection: Connection,
+    /// The protocol version they speak.
+    version: u8,
+    /// The capabilities agreed on in their handshake.
+    capabilities: Capabilities,
+    /// The queue position they were last told about, or 0 if they are not queued.
+    position: u16,
+}
+
+impl Waiting {
+    /// Tells the player where they stand in the queue, unless they already know or their client
+    /// is too old to understand.
+    fn tell_position(&mut self, position: u16) {
+        if self.position != position && self.version >= QUEUE_VERSION {
+            self.connection
+                .send(ClientBoundPacket::QueuePosition { position })
+                .ok();
+        }
+        self.position = position;
+    }
+}
+
+/// Everyone connected who is not in a game: the matchmaking queue in the order players joined
+/// it, the players waiting in a room of their own for someone to join, and those who are still
+/// connected but waiting for neither.
+#[derive(Default)]
+struct Lobby {
+    queue: Vec<Waiting>,
+    rooms: Vec<Room>,
+    idle: Vec<Waiting>,
+    /// Room hosts and the players who joined them, whose games are yet to be started.
+    matched: Vec<(Waiting, Waiting)>,
+    /// The games being played, for spectators to pick from.
+    games: Games,
+}
+
+/// A private room, and the player who opened it.
+struct Room {
+    code: String,
+    host: Waiting,
+}
+
+/// Which part of the lobby a player is in.
+#[derive(Clone, Copy, Debug)]
+enum Place {
+    Queue(usize),
+    Room(usize),
+    Idle(usize),
+}
+
+impl Lobby {
+    /// Waits for the next packet from anyone in the lobby, or `None` once their connection is
+    /// gone, along with where they are.
+    fn next_packet(
+        &mut self,
+    ) -> impl Future<Output = (Place, Option<Result<ServerBoundPacket, ProtocolError>>)> + '_ {
+        std::future::poll_fn(move |cx| {
+            let queue = self.queue.iter_mut().enumerate();
+            let queue = queue.map(|(index, waiting)| (Place::Queue(index), waiting));
+            let rooms = self.rooms.iter_mut().enumerate();
+            let rooms = rooms.map(|(index, room)| (Place::Room(index), &mut room.host));
+            let idle = self.idle.iter_mut().enumerate();
+            let idle = idle.map(|(index, waiting)| (Place::Idle(index), waiting));
+            for (place, waiting) in queue.chain(rooms).chain(idle) {
+                if let Poll::Ready(packet) = waiting.connection.packets.poll_recv(cx) {
+                    return Poll::Ready((place, packet));
+                }
+            }
+            Poll::Pending
+        })
+    }
+
+    /// Takes a player out of the lobby, telling them if that takes them out of the queue and
+    /// closing their room if they had one.
+    fn remove(&mut self, place: Place) -> Waiting {
+        match place {
+            Place::Queue(index) => {
+                let mut waiting = self.queue.remove(index);
+                waiting.tell_position(0);
+                waiting
+            }
+            Place::Room(index) => {
+                let room = self.rooms.remove(index);
+                println!("closed room {}", room.code);
+                room.host
+            }
+            Place::Idle(index) => self.idle.remove(index),
+        }
+    }
+
+    fn get_mut(&mut self, place: Place) -> &mut Waiting {
+        match place {
+            Place::Queue(index) => &mut self.queue[index],
+            Place::Room(index) => &mut self.rooms[index].host,
+            Place::Idle(index) => &mut self.idle[index],
+        }
+    }
+
+    /// Makes up a join code that no open room has.
+    fn room_code(&self) -> String {
+        let mut rng = rand::thread_rng();
+        loop {
+            let code: String = (0..ROOM_CODE_LEN)
+                .map(|_| ROOM_CODE_CHARS[rng.gen_range(0..ROOM_CODE_CHARS.len())] as char)
+                .collect();
+            if self.rooms.iter().all(|room| room.code != code) {
+                return code;
+            }
+        }
+    }
+
+    fn handle(&mut self, place: Place, packet: Option<Result<ServerBoundPacket, ProtocolError>>) {
+        let (code, message) = match packet {
+            None => {
+                let waiting = self.remove(place);
+                println!("{} left the lobby", waiting.name);
+                return;
+            }
+            Some(Ok(ServerBoundPacket::Queue)) => {
+                if !matches!(place, Place::Queue(_)) {
+                    let waiting = self.remove(place);
+                    println!("{} joined the queue", waiting.name);
+                    self.queue.push(waiting);
+                }
+                return;
+            }
+            Some(Ok(ServerBoundPacket::CancelQueue)) => {
+                if !matches!(place, Place::Idle(_)) {
+                    let waiting = self.remove(place);
+                    println!("{} stopped waiting for an opponent", waiting.name);
+                    self.idle.push(waiting);
+                }
+                return;
+            }
+            Some(Ok(ServerBoundPacket::CreateRoom)) => {
+                let host = self.remove(place);
+                let code = self.room_code();
+                println!("{} opened room {}", host.name, code);
+                let packet = ClientBoundPacket::RoomCreated { code: code.clone() };
+                host.connection.send(packet).ok();
+                self.rooms.push(Room { code, host });
+                return;
+            }
+            Some(Ok(ServerBoundPacket::JoinRoom { code })) => {
+                let code = code.trim().to_uppercase();
+                match self.rooms.iter().position(|room| room.code == code) {
+                    Some(index) if matches!(place, Place::Room(host) if host == index) => (
+                        ErrorCode::UnexpectedPacket,
+                        "you cannot join your own room".to_string(),
+                    ),
+                    Some(_) => {
+                        let guest = self.remove(place);
+                        // taking the guest out may have closed a room of theirs and moved this one
+                        let index = self.rooms.iter().position(|room| room.code == code);
+                        let room = self.rooms.remove(index.unwrap());
+                        println!("{} joined room {}", guest.name, room.code);
+                        self.matched.push((room.host, guest));
+                        return;
                     }
-                    _ => {
-                        panic!("Expected move packet")
+                    None => (
+                        ErrorCode::UnknownRoom,
+                        format!("there is no open room with the code {}", code),
+                    ),
+                }
+            }
+            Some(Ok(packet @ (ServerBoundPacket::ListGames | ServerBoundPacket::Watch { .. })))
+                if !self
+                    .get_mut(place)
+                    .capabilities
+                    .contains(Capabilities::SPECTATE) =>
+            {
+                (
+                    ErrorCode::UnexpectedPacket,
+                    format!(
+                        "{:?} needs spectating, which the handshake left out",
+                        packet
+                    ),
+                )
+            }
+            Some(Ok(ServerBoundPacket::ListGames)) => {
+                let mut games: Vec<_> = self
+                    .games
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(&id, game)| GameSummary {
+                        id,
+                        red: game.red.clone(),
+                        yellow: game.yellow.clone(),
+                    })
+                    .collect();
+                games.sort_by_key(|game| game.id);
+                let packet = ClientBoundPacket::GameList { games };
+                self.get_mut(place).connection.send(packet).ok();
+                return;
+            }
+            Some(Ok(ServerBoundPacket::Watch { id })) => {
+                let game = self.games.lock().unwrap().get(&id).cloned();
+                match game {
+                    Some(game) => {
+                        let waiting = self.remove(place);
+                        println!("{} is watching game {}", waiting.name, id);
+                        let spectator = Spectator {
+                            connection: waiting.connection,
+                            capabilities: waiting.capabilities,
+                        };
+                        let Err(SendError(spectator)) = game.spectators.send(spectator) else {
+                            return;
+                        };
+                        // the game ended since it was looked up, so they stay in the lobby
+                        let waiting = Waiting {
+                            connection: spectator.connection,
+                            ..waiting
+                        };
+                        let packet = ClientBoundPacket::Error {
+                            code: ErrorCode::UnknownGame,
+                            message: format!("game {} just ended", id),
+                        };
+                        waiting.connection.send(packet).ok();
+                        self.idle.push(waiting);
+                        return;
                     }
+                    None => (
+                        ErrorCode::UnknownGame,
+                        format!("there is no game {} to watch", id),
+                    ),
                 }
             }
+            Some(Ok(packet)) => (
+                ErrorCode::UnexpectedPacket,
+                format!("{:?} is not allowed outside of a game", packet),
+            ),
+            Some(Err(e)) => (ErrorCode::MalformedPacket, e.to_string()),
+        };
+        self.get_mut(place)
+            .connection
+            .send(ClientBoundPacket::Error { code, message })
+            .ok();
+    }
+
+    /// Handles everything that has already arrived, so that players who left are gone before
+    /// anyone is matched with them.
+    fn catch_up(&mut self) {
+        while let Some((place, packet)) = self.next_packet().now_or_never() {
+            self.handle(place, packet);
         }
     }
-}
 
-async fn read_serverbound_packet(stream: &mut TcpStream) -> ServerBoundPacket {
-    let len = match stream.read_u8().await {
-        Ok(len) => len,
-        Err(_) => {
-            panic!("Failed to read length of packet. This could mean the client disconnected.");
+    /// Takes two players who should now play each other: a room host and the player who joined
+    /// them, or else the two at the front of the queue.
+    fn next_pair(&mut self) -> Option<(Waiting, Waiting)> {
+        if let Some(pair) = self.matched.pop() {
+            return Some(pair);
         }
+        if self.queue.len() < 2 {
+            return None;
+        }
+        let mut pair = self.queue.drain(..2);
+        Some((pair.next().unwrap(), pair.next().unwrap()))
+    }
+
+    fn tell_positions(&mut self) {
+        for (index, waiting) in self.queue.iter_mut().enumerate() {
+            waiting.tell_position(u16::try_from(index + 1).unwrap_or(u16::MAX));
+        }
+    }
+}
+
+/// Runs the lobby: takes in players as they finish their handshake and starts a game for the
+/// first two in the queue whenever there are two, and for every room someone joins. Runs on its
+/// own task, so the listeners never wait on it.
+async fn run_lobby(
+    mut arrivals: mpsc::UnboundedReceiver<Waiting>,
+    settings: Settings,
+    sessions: Sessions,
+    games: Games,
+) {
+    let mut lobby = Lobby {
+        games: games.clone(),
+        ..Lobby::default()
     };
-    let mut buf = vec![0; len as usize];
-    stream.read_exact(&mut buf).await.unwrap();
-    ServerBoundPacket::deserialize(&buf)
+    loop {
+        tokio::select! {
+            arrival = arrivals.recv() => match arrival {
+                Some(waiting) if waiting.version >= ROOMS_VERSION => lobby.idle.push(waiting),
+                Some(waiting) => lobby.queue.push(waiting),
+                None => return,
+            },
+            (place, packet) = lobby.next_packet() => lobby.handle(place, packet),
+        }
+        lobby.catch_up();
+        while let Some((first, second)) = lobby.next_pair() {
+            println!("paired {} with {}, starting game", first.name, second.name);
+            let player1 = Player::human(first.connection, first.name, first.capabilities);
+            let player2 = Player::human(second.connection, second.name, second.capabilities);
+            let (sessions, games) = (sessions.clone(), games.clone());
+            tokio::spawn(play_game(player1, player2, settings, sessions, games));
+        }
+        lobby.tell_positions();
+    }
 }
 
-#[tokio::main]
-async fn main() -> io::Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:60941").await?;
+/// Reads `--rows`, `--cols`, `--connect`, `--max-violations`, `--grace-period` (in seconds),
+/// `--max-frame-size` (in bytes), `--port`, `--json-port`, `--ws-port` and `--spectator-chat`
+/// (`true` or `false`), defaulting to a standard board, three strikes, half a minute to reconnect,
+/// `DEFAULT_MAX_FRAME_SIZE`, ports 60941 to 60943 and letting spectators read the chat.
+fn parse_settings() -> Result<Settings, Box<dyn Error>> {
+    let (mut rows, mut cols, mut connect) = (6, 7, 4);
+    let mut max_violations = 3;
+    let mut grace_period = 30;
+    let mut max_frame_size = DEFAULT_MAX_FRAME_SIZE;
+    let (mut port, mut json_port, mut ws_port) = (60941, 60942, 60943);
+    let mut spectator_chat = true;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--rows" => rows = value.parse()?,
+            "--cols" => cols = value.parse()?,
+            "--connect" => connect = value.parse()?,
+            "--max-violations" => max_violations = value.parse()?,
+            "--grace-period" => grace_period = value.parse()?,
+            "--max-frame-size" => max_frame_size = value.parse()?,
+            "--port" => port = value.parse()?,
+            "--json-port" => json_port = value.parse()?,
+            "--ws-port" => ws_port = value.parse()?,
+            "--spectator-chat" => spectator_chat = value.parse()?,
+            _ => return Err(format!("unknown argument {}", arg).into()),
+        }
+    }
+    Ok(Settings {
+        config: BoardConfig::new(rows, cols, connect)?,
+        max_violations,
+        grace_period: Duration::from_secs(grace_period),
+        max_frame_size,
+        port,
+        json_port,
+        ws_port,
+        spectator_chat,
+    })
+}
 
+/// Hands every connection `listener` accepts to `handshake`.
+async fn serve(
+    listener: TcpListener,
+    protocol: Protocol,
+    settings: Settings,
+    sessions: Sessions,
+    games: Games,
+    lobby: mpsc::UnboundedSender<Waiting>,
+) -> io::Result<()> {
     loop {
         let (socket, _) = listener.accept().await?;
-        println!("accepted connection, waiting for second player");
-        let (socket2, _) = listener.accept().await?;
-        println!("accepted second connection, starting game");
+        println!("accepted {:?} connection", protocol);
+        let (sessions, games, lobby) = (sessions.clone(), games.clone(), lobby.clone());
         tokio::spawn(async move {
-            play_game(socket, socket2).await;
+            match Connection::accept(socket, protocol, settings.max_frame_size).await {
+                Ok(connection) => handshake(connection, settings, sessions, games, lobby).await,
+                Err(e) => println!("dropping {:?} connection: {}", protocol, e),
+            }
         });
     }
 }
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let settings = parse_settings()?;
+    let listener = TcpListener::bind(("0.0.0.0", settings.port)).await?;
+    let json_listener = TcpListener::bind(("0.0.0.0", settings.json_port)).await?;
+    let ws_listener = TcpListener::bind(("0.0.0.0", settings.ws_port)).await?;
+    let sessions = Sessions::default();
+    let games = Games::default();
+
+    let (lobby, arrivals) = mpsc::unbounded_channel();
+    tokio::spawn(run_lobby(
+        arrivals,
+        settings,
+        sessions.clone(),
+        games.clone(),
+    ));
+
+    tokio::spawn(serve(
+        json_listener,
+        Protocol::Json,
+        settings,
+        sessions.clone(),
+        games.clone(),
+        lobby.clone(),
+    ));
+    tokio::spawn(serve(
+        ws_listener,
+        Protocol::WebSocket,
+        settings,
+        sessions.clone(),
+        games.clone(),
+        lobby.clone(),
+    ));
+    serve(listener, Protocol::Binary, settings, sessions, games, lobby).await?;
+    Ok(())
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::{join_with, recv, Writer};
+use connect_4::{Capabilities, ClientBoundPacket, Color, ErrorCode, ServerBoundPacket};
+
+const PORT: u16 = 59941;
+
+async fn say(writer: &mut Writer, text: &str) {
+    let text = text.to_string();
+    writer.send(ServerBoundPacket::Chat { text }).await.unwrap();
+}
+
+fn chat(from: &str, text: &str) -> ClientBoundPacket {
+    ClientBoundPacket::Chat {
+        from: from.to_string(),
+        text: text.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_chat() {
+    let _server = common::Server::start(PORT);
+    let (mut host, mut host_writer) = join_with(PORT, "host", Capabilities::CHAT).await;
+    host_writer
+        .send(ServerBoundPacket::CreateRoom)
+        .await
+        .unwrap();
+    let ClientBoundPacket::RoomCreated { code } = recv(&mut host).await else {
+        panic!("expected a join code");
+    };
+    let (mut guest, mut guest_writer) = join_with(PORT, "guest", Capabilities::CHAT).await;
+    guest_writer
+        .send(ServerBoundPacket::JoinRoom { code })
+        .await
+        .unwrap();
+    let ClientBoundPacket::GameStart { your_color, .. } = recv(&mut host).await else {
+        panic!("expected the game to start");
+    };
+    assert!(matches!(
+        recv(&mut host).await,
+        ClientBoundPacket::State { .. }
+    ));
+    assert!(matches!(
+        recv(&mut guest).await,
+        ClientBoundPacket::GameStart { .. }
+    ));
+    assert!(matches!(
+        recv(&mut guest).await,
+        ClientBoundPacket::State { .. }
+    ));
+
+    // one spectator can read chat and the other cannot
+    let mut spectators = Vec::new();
+    for capabilities in [
+        Capabilities::SPECTATE | Capabilities::CHAT,
+        Capabilities::SPECTATE,
+    ] {
+        let (mut reader, mut writer) = join_with(PORT, "watcher", capabilities).await;
+        writer.send(ServerBoundPacket::ListGames).await.unwrap();
+        let ClientBoundPacket::GameList { games } = recv(&mut reader).await else {
+            panic!("expected the list of games");
+        };
+        let id = games[0].id;
+        writer.send(ServerBoundPacket::Watch { id }).await.unwrap();
+        assert!(matches!(
+            recv(&mut reader).await,
+            ClientBoundPacket::State { .. }
+        ));
+        spectators.push((reader, writer));
+    }
+
+    say(&mut host_writer, "good luck").await;
+    assert_eq!(recv(&mut host).await, chat("host", "good luck"));
+    assert_eq!(recv(&mut guest).await, chat("host", "good luck"));
+    assert_eq!(recv(&mut spectators[0].0).await, chat("host", "good luck"));
+
+    // nothing that could mess with someone's terminal gets through
+    say(&mut guest_writer, "\x1B[2Jgotcha").await;
+    assert!(matches!(
+        recv(&mut guest).await,
+        ClientBoundPacket::Error {
+            code: ErrorCode::ChatRejected,
+            ..
+        }
+    ));
+
+    // and neither does a flood
+    for _ in 0..5 {
+        say(&mut guest_writer, "hi").await;
+        assert_eq!(recv(&mut guest).await, chat("guest", "hi"));
+        assert_eq!(recv(&mut host).await, chat("guest", "hi"));
+    }
+    say(&mut guest_writer, "hi").await;
+    assert!(matches!(
+        recv(&mut guest).await,
+        ClientBoundPacket::Error {
+            code: ErrorCode::ChatRejected,
+            ..
+        }
+    ));
+
+    // refused chat does not count towards forfeiting, so the game goes on
+    let red_writer = match your_color {
+        Color::Red => &mut host_writer,
+        Color::Yellow => &mut guest_writer,
+    };
+    red_writer
+        .send(ServerBoundPacket::Move { col: 3 })
+        .await
+        .unwrap();
+    let moved = ClientBoundPacket::Move {
+        col: 3,
+        color: Color::Red,
+    };
+    assert_eq!(recv(&mut spectators[1].0).await, moved);
+    for _ in 0..5 {
+        assert_eq!(recv(&mut spectators[0].0).await, chat("guest", "hi"));
+    }
+    assert_eq!(recv(&mut spectators[0].0).await, moved);
+}
@@ -0,0 +1,86 @@
+// compiled into each test separately, and no test uses all of it
+#![allow(dead_code)]
+
+use connect_4::codec::{self, PacketReader, PacketWriter};
+use connect_4::{
+    Capabilities, ClientBoundPacket, ServerBoundPacket, DEFAULT_MAX_FRAME_SIZE, PROTOCOL_VERSION,
+};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tokio::io::{ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+
+pub type Reader = PacketReader<ReadHalf<TcpStream>, ClientBoundPacket>;
+pub type Writer = PacketWriter<WriteHalf<TcpStream>, ServerBoundPacket>;
+
+/// Runs the server on ports of its own and stops it when dropped.
+pub struct Server(Child);
+
+impl Server {
+    pub fn start(port: u16) -> Self {
+        Self::start_with(port, &[])
+    }
+
+    /// Like `start`, passing `args` on to the server as well.
+    pub fn start_with(port: u16, args: &[&str]) -> Self {
+        let child = Command::new(env!("CARGO_BIN_EXE_server"))
+            .args(["--port", &port.to_string()])
+            .args(["--json-port", &(port + 1).to_string()])
+            .args(["--ws-port", &(port + 2).to_string()])
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("failed to start the server");
+        Server(child)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+    }
+}
+
+pub fn init(name: &str) -> ServerBoundPacket {
+    init_with(name, Capabilities::VARIANTS)
+}
+
+pub fn init_with(name: &str, capabilities: Capabilities) -> ServerBoundPacket {
+    ServerBoundPacket::Init {
+        name: name.to_string(),
+        bot: None,
+        version: PROTOCOL_VERSION,
+        capabilities,
+    }
+}
+
+/// Connects to the binary listener on `port`, retrying while the server starts up.
+pub async fn connect(port: u16) -> (Reader, Writer) {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)).await {
+            return codec::split(stream, DEFAULT_MAX_FRAME_SIZE);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("could not connect to the server");
+}
+
+pub async fn recv(reader: &mut Reader) -> ClientBoundPacket {
+    reader.read().await.unwrap().unwrap()
+}
+
+/// Connects, says hello, and returns once the server has welcomed us into the lobby.
+pub async fn join(port: u16, name: &str) -> (Reader, Writer) {
+    join_with(port, name, Capabilities::VARIANTS).await
+}
+
+/// Like `join`, offering `capabilities` instead.
+pub async fn join_with(port: u16, name: &str, capabilities: Capabilities) -> (Reader, Writer) {
+    let (mut reader, mut writer) = connect(port).await;
+    writer.send(init_with(name, capabilities)).await.unwrap();
+    assert!(matches!(
+        recv(&mut reader).await,
+        ClientBoundPacket::Welcome { .. }
+    ));
+    (reader, writer)
+}
@@ -0,0 +1,329 @@
+mod common;
+
+use common::{connect, join, recv, Reader, Writer};
+use connect_4::bot::Difficulty;
+use connect_4::{
+    Board, BoardConfig, Capabilities, ClientBoundPacket, Color, EndReason, ErrorCode, GameResult,
+    ServerBoundPacket, PROTOCOL_VERSION,
+};
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// One side of a game between two test clients.
+struct Seat {
+    name: &'static str,
+    reader: Reader,
+    writer: Writer,
+    /// For resuming the game after dropping out of it.
+    token: u64,
+}
+
+impl Seat {
+    async fn send(&mut self, packet: ServerBoundPacket) {
+        self.writer.send(packet).await.unwrap();
+    }
+
+    async fn recv(&mut self) -> ClientBoundPacket {
+        recv(&mut self.reader).await
+    }
+
+    /// Receives the next packet, which should be an error with `code`.
+    async fn refused(&mut self, code: ErrorCode) {
+        match self.recv().await {
+            ClientBoundPacket::Error { code: got, .. } if got == code => {}
+            packet => panic!("expected a {:?} error, got {:?}", code, packet),
+        }
+    }
+}
+
+/// Starts a game between "host" and "guest" through a private room, and returns red's seat and
+/// then yellow's once both have been sent the board.
+async fn start_game(port: u16) -> (Seat, Seat) {
+    let (mut host, mut host_writer) = join(port, "host").await;
+    host_writer
+        .send(ServerBoundPacket::CreateRoom)
+        .await
+        .unwrap();
+    let ClientBoundPacket::RoomCreated { code } = recv(&mut host).await else {
+        panic!("expected a join code");
+    };
+    let (guest, mut guest_writer) = join(port, "guest").await;
+    guest_writer
+        .send(ServerBoundPacket::JoinRoom { code })
+        .await
+        .unwrap();
+    let mut seats = Vec::new();
+    let players = [("host", host, host_writer), ("guest", guest, guest_writer)];
+    for (name, mut reader, writer) in players {
+        let ClientBoundPacket::GameStart {
+            your_color, token, ..
+        } = recv(&mut reader).await
+        else {
+            panic!("expected the game to start");
+        };
+        assert!(matches!(
+            recv(&mut reader).await,
+            ClientBoundPacket::State { .. }
+        ));
+        let seat = Seat {
+            name,
+            reader,
+            writer,
+            token,
+        };
+        seats.push((your_color, seat));
+    }
+    seats.sort_by_key(|(color, _)| color.index());
+    let mut seats = seats.into_iter().map(|(_, seat)| seat);
+    (seats.next().unwrap(), seats.next().unwrap())
+}
+
+/// Starts a game against a bot and returns once it has, along with our color.
+async fn play_bot(port: u16, difficulty: Difficulty) -> (Reader, Writer, Color) {
+    let (mut reader, mut writer) = connect(port).await;
+    let init = ServerBoundPacket::Init {
+        name: "human".to_string(),
+        bot: Some(difficulty),
+        version: PROTOCOL_VERSION,
+        capabilities: Capabilities::NONE,
+    };
+    writer.send(init).await.unwrap();
+    assert!(matches!(
+        recv(&mut reader).await,
+        ClientBoundPacket::Welcome { .. }
+    ));
+    let ClientBoundPacket::GameStart { your_color, .. } = recv(&mut reader).await else {
+        panic!("expected the game to start");
+    };
+    assert!(matches!(
+        recv(&mut reader).await,
+        ClientBoundPacket::State { .. }
+    ));
+    (reader, writer, your_color)
+}
+
+#[tokio::test]
+async fn test_bot_moves_while_asked_for_state() {
+    let _server = common::Server::start(57941);
+    let (mut reader, mut writer, color) = play_bot(57941, Difficulty::Hard).await;
+    if color == Color::Red {
+        writer
+            .send(ServerBoundPacket::Move { col: 3 })
+            .await
+            .unwrap();
+    }
+    // asking for the board over and over does not hold up the bot's search
+    let flood = tokio::spawn(async move {
+        loop {
+            if writer.send(ServerBoundPacket::RequestState).await.is_err() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    });
+    let moved = timeout(Duration::from_secs(20), async {
+        loop {
+            match recv(&mut reader).await {
+                ClientBoundPacket::Move { color: moved, .. } if moved != color => return,
+                _ => continue,
+            }
+        }
+    })
+    .await;
+    flood.abort();
+    moved.expect("the bot never moved");
+}
+
+#[tokio::test]
+async fn test_illegal_moves_and_violations() {
+    let _server = common::Server::start(57951);
+    let (mut red, mut yellow) = start_game(57951).await;
+
+    yellow.send(ServerBoundPacket::Move { col: 3 }).await;
+    yellow.refused(ErrorCode::NotYourTurn).await;
+    red.send(ServerBoundPacket::Move { col: 7 }).await;
+    red.refused(ErrorCode::IllegalMove).await;
+    // refused moves change nothing, so red can still play
+    red.send(ServerBoundPacket::Move { col: 0 }).await;
+    let moved = ClientBoundPacket::Move {
+        col: 0,
+        color: Color::Red,
+    };
+    assert_eq!(red.recv().await, moved);
+    assert_eq!(yellow.recv().await, moved);
+
+    // the default allows three bad packets, counting the move off the board, and the fourth
+    // costs the game
+    for _ in 0..2 {
+        red.send(ServerBoundPacket::Move { col: 1 }).await;
+        red.refused(ErrorCode::NotYourTurn).await;
+    }
+    red.send(ServerBoundPacket::CreateRoom).await;
+    red.refused(ErrorCode::UnexpectedPacket).await;
+    let result = ClientBoundPacket::GameResult {
+        result: GameResult::YellowWin,
+        col: None,
+        color: Color::Red,
+        reason: EndReason::Forfeit,
+        lines: Vec::new(),
+    };
+    assert_eq!(red.recv().await, result);
+    assert_eq!(yellow.recv().await, result);
+}
+
+#[tokio::test]
+async fn test_forfeit() {
+    let _server = common::Server::start(57961);
+    let (mut red, mut yellow) = start_game(57961).await;
+    red.send(ServerBoundPacket::Move { col: 3 }).await;
+    assert!(matches!(red.recv().await, ClientBoundPacket::Move { .. }));
+    assert!(matches!(
+        yellow.recv().await,
+        ClientBoundPacket::Move { .. }
+    ));
+
+    // forfeiting is allowed whoever's turn it is
+    red.send(ServerBoundPacket::Forfeit).await;
+    let result = ClientBoundPacket::GameResult {
+        result: GameResult::YellowWin,
+        col: None,
+        color: Color::Red,
+        reason: EndReason::Forfeit,
+        lines: Vec::new(),
+    };
+    assert_eq!(red.recv().await, result);
+    assert_eq!(yellow.recv().await, result);
+}
+
+#[tokio::test]
+async fn test_disconnect_awards_the_win() {
+    let _server = common::Server::start_with(57971, &["--grace-period", "1"]);
+    let (mut red, yellow) = start_game(57971).await;
+    drop(yellow);
+    // nothing is decided until the grace period is up
+    let early = timeout(Duration::from_millis(500), red.recv()).await;
+    assert!(early.is_err(), "got {:?} during the grace period", early);
+    let result = timeout(Duration::from_secs(3), red.recv()).await;
+    assert_eq!(
+        result.expect("the win was never awarded"),
+        ClientBoundPacket::GameResult {
+            result: GameResult::RedWin,
+            col: None,
+            color: Color::Yellow,
+            reason: EndReason::Disconnected,
+            lines: Vec::new(),
+        }
+    );
+}
+
+/// Reconnects and asks for the seat that `token` was handed out with.
+async fn resume(port: u16, token: u64) -> (Reader, Writer) {
+    let (reader, mut writer) = connect(port).await;
+    writer
+        .send(ServerBoundPacket::Resume { token })
+        .await
+        .unwrap();
+    (reader, writer)
+}
+
+#[tokio::test]
+async fn test_resume() {
+    let _server = common::Server::start_with(57981, &["--grace-period", "2"]);
+    let (mut red, yellow) = start_game(57981).await;
+    red.send(ServerBoundPacket::Move { col: 3 }).await;
+    assert!(matches!(red.recv().await, ClientBoundPacket::Move { .. }));
+
+    // yellow drops before seeing red's move, and is brought up to date on their return
+    let (name, token) = (yellow.name, yellow.token);
+    drop(yellow);
+    let (reader, writer) = resume(57981, token).await;
+    let mut yellow = Seat {
+        name,
+        reader,
+        writer,
+        token,
+    };
+    let ClientBoundPacket::State {
+        to_move,
+        move_number,
+        ..
+    } = yellow.recv().await
+    else {
+        panic!("expected the board");
+    };
+    assert_eq!((to_move, move_number), (Color::Yellow, 1));
+    yellow.send(ServerBoundPacket::Move { col: 3 }).await;
+    let moved = ClientBoundPacket::Move {
+        col: 3,
+        color: Color::Yellow,
+    };
+    assert_eq!(yellow.recv().await, moved);
+    assert_eq!(red.recv().await, moved);
+
+    // once the grace period is up the seat is gone, and so is the game
+    drop(yellow);
+    assert!(matches!(
+        red.recv().await,
+        ClientBoundPacket::GameResult {
+            reason: EndReason::Disconnected,
+            ..
+        }
+    ));
+    let (mut reader, _writer) = resume(57981, token).await;
+    assert!(matches!(
+        recv(&mut reader).await,
+        ClientBoundPacket::Error {
+            code: ErrorCode::UnknownSession,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_request_state() {
+    let _server = common::Server::start(57991);
+    let (mut red, mut yellow) = start_game(57991).await;
+    for (col, color) in [(3, Color::Red), (4, Color::Yellow), (3, Color::Red)] {
+        let (mover, other) = match color {
+            Color::Red => (&mut red, &mut yellow),
+            Color::Yellow => (&mut yellow, &mut red),
+        };
+        mover.send(ServerBoundPacket::Move { col }).await;
+        assert!(matches!(mover.recv().await, ClientBoundPacket::Move { .. }));
+        assert!(matches!(other.recv().await, ClientBoundPacket::Move { .. }));
+    }
+
+    // either player may ask, whoever's turn it is
+    let state = ClientBoundPacket::State {
+        red: red.name.to_string(),
+        yellow: yellow.name.to_string(),
+        config: BoardConfig::STANDARD,
+        cells: Board::from_moves("454").unwrap().cells(),
+        to_move: Color::Yellow,
+        move_number: 3,
+    };
+    red.send(ServerBoundPacket::RequestState).await;
+    assert_eq!(red.recv().await, state);
+    yellow.send(ServerBoundPacket::RequestState).await;
+    assert_eq!(yellow.recv().await, state);
+
+    // and the answer keeps up with the game
+    yellow.send(ServerBoundPacket::Move { col: 0 }).await;
+    assert!(matches!(
+        yellow.recv().await,
+        ClientBoundPacket::Move { .. }
+    ));
+    assert!(matches!(red.recv().await, ClientBoundPacket::Move { .. }));
+    red.send(ServerBoundPacket::RequestState).await;
+    let ClientBoundPacket::State {
+        cells,
+        to_move,
+        move_number,
+        ..
+    } = red.recv().await
+    else {
+        panic!("expected the board");
+    };
+    assert_eq!(cells, Board::from_moves("4541").unwrap().cells());
+    assert_eq!((to_move, move_number), (Color::Red, 4));
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use common::{connect, init, init_with};
+use connect_4::{Capabilities, ClientBoundPacket, Deserialize, ErrorCode, ServerBoundPacket};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+const PORT: u16 = 58941;
+
+#[tokio::test]
+async fn test_refused_connections_are_closed() {
+    let _server = common::Server::start(PORT);
+    let (mut reader, mut writer) = connect(PORT).await;
+    writer
+        .send(ServerBoundPacket::Resume { token: 1 })
+        .await
+        .unwrap();
+    let Ok(ClientBoundPacket::Error { code, .. }) = reader.read().await.unwrap() else {
+        panic!("expected the resume to be refused");
+    };
+    assert_eq!(code, ErrorCode::UnknownSession);
+    // the server hangs up rather than leaving us waiting
+    let end = timeout(Duration::from_secs(3), reader.read()).await;
+    assert!(end.expect("the connection was left open").is_err());
+
+    let stream = TcpStream::connect(("127.0.0.1", PORT + 1)).await.unwrap();
+    let mut json = BufReader::new(stream);
+    json.get_mut()
+        .write_all(b"{\"type\": \"init\", \"name\": \"x\", \"bot\": null, \"version\": 99, \"capabilities\": 0}\n")
+        .await
+        .unwrap();
+    let mut line = String::new();
+    json.read_line(&mut line).await.unwrap();
+    assert!(line.contains("version_mismatch"), "{}", line);
+    let mut rest = Vec::new();
+    let read = timeout(Duration::from_secs(3), json.read_to_end(&mut rest)).await;
+    assert_eq!(read.expect("the connection was left open").unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_version_1_client_is_told_to_update() {
+    let _server = common::Server::start(58951);
+    // wait for the server to come up before speaking the old framing ourselves
+    drop(connect(58951).await);
+    let mut stream = TcpStream::connect(("127.0.0.1", 58951)).await.unwrap();
+    // an `Init` as version 1 sent it: one length byte, the opcode, then the name
+    stream.write_all(&[4, 0, b'o', b'l', b'd']).await.unwrap();
+
+    let len = stream.read_u8().await.unwrap();
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf).await.unwrap();
+    let Ok(ClientBoundPacket::Error { message, .. }) = ClientBoundPacket::deserialize(&buf) else {
+        panic!("expected a refusal, got {:?}", buf);
+    };
+    assert!(message.contains("Please update your client"), "{}", message);
+    let mut rest = Vec::new();
+    let read = timeout(Duration::from_secs(3), stream.read_to_end(&mut rest)).await;
+    assert_eq!(read.expect("the connection was left open").unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_variant_board_needs_variants() {
+    let _server = common::Server::start_with(58961, &["--rows", "7", "--cols", "8"]);
+    let (mut reader, mut writer) = connect(58961).await;
+    writer
+        .send(init_with("plain", Capabilities::CHAT))
+        .await
+        .unwrap();
+    let Ok(ClientBoundPacket::Error { code, .. }) = reader.read().await.unwrap() else {
+        panic!("expected the client to be refused");
+    };
+    assert_eq!(code, ErrorCode::MissingCapability);
+    assert!(reader.read().await.is_err());
+
+    let (mut reader, mut writer) = connect(58961).await;
+    writer.send(init("variants")).await.unwrap();
+    assert!(matches!(
+        reader.read().await.unwrap(),
+        Ok(ClientBoundPacket::Welcome { .. })
+    ));
+}
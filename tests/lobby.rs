@@ -0,0 +1,242 @@
+mod common;
+
+use common::{connect, init_with, join, recv, Reader, Writer};
+use connect_4::{
+    Capabilities, ClientBoundPacket, Color, EndReason, ErrorCode, GameResult, ServerBoundPacket,
+    PROTOCOL_VERSION,
+};
+
+/// Joins the lobby and then the queue.
+async fn queue(port: u16, name: &str) -> (Reader, Writer) {
+    let (reader, mut writer) = join(port, name).await;
+    writer.send(ServerBoundPacket::Queue).await.unwrap();
+    (reader, writer)
+}
+
+#[tokio::test]
+async fn test_queue_positions_cancel_and_pruning() {
+    let _server = common::Server::start(62941);
+    let (mut first, mut first_writer) = queue(62941, "first").await;
+    assert_eq!(
+        recv(&mut first).await,
+        ClientBoundPacket::QueuePosition { position: 1 }
+    );
+
+    // stepping out of the queue keeps the connection, and nobody is paired with us meanwhile
+    first_writer
+        .send(ServerBoundPacket::CancelQueue)
+        .await
+        .unwrap();
+    assert_eq!(
+        recv(&mut first).await,
+        ClientBoundPacket::QueuePosition { position: 0 }
+    );
+    let (mut second, second_writer) = queue(62941, "second").await;
+    assert_eq!(
+        recv(&mut second).await,
+        ClientBoundPacket::QueuePosition { position: 1 }
+    );
+
+    // the second player hangs up, so the next one to join is not matched with them
+    drop((second, second_writer));
+    let (mut third, _third_writer) = queue(62941, "third").await;
+    assert_eq!(
+        recv(&mut third).await,
+        ClientBoundPacket::QueuePosition { position: 1 }
+    );
+
+    first_writer.send(ServerBoundPacket::Queue).await.unwrap();
+    let ClientBoundPacket::GameStart { opponent, .. } = recv(&mut third).await else {
+        panic!("expected the game to start");
+    };
+    assert_eq!(opponent, "first");
+    let ClientBoundPacket::GameStart { opponent, .. } = recv(&mut first).await else {
+        panic!("expected the game to start");
+    };
+    assert_eq!(opponent, "third");
+}
+
+#[tokio::test]
+async fn test_private_room() {
+    let _server = common::Server::start(63941);
+    let (mut host, mut host_writer) = join(63941, "host").await;
+    host_writer
+        .send(ServerBoundPacket::CreateRoom)
+        .await
+        .unwrap();
+    let ClientBoundPacket::RoomCreated { code } = recv(&mut host).await else {
+        panic!("expected a join code");
+    };
+
+    // someone in the queue is not matched with the host
+    let (mut stranger, _stranger_writer) = queue(63941, "stranger").await;
+    assert_eq!(
+        recv(&mut stranger).await,
+        ClientBoundPacket::QueuePosition { position: 1 }
+    );
+
+    let (mut guest, mut guest_writer) = join(63941, "guest").await;
+    guest_writer
+        .send(ServerBoundPacket::JoinRoom {
+            code: "NOPE42".to_string(),
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv(&mut guest).await,
+        ClientBoundPacket::Error {
+            code: ErrorCode::UnknownRoom,
+            ..
+        }
+    ));
+
+    // codes are not case sensitive
+    guest_writer
+        .send(ServerBoundPacket::JoinRoom {
+            code: code.to_lowercase(),
+        })
+        .await
+        .unwrap();
+    let ClientBoundPacket::GameStart { opponent, .. } = recv(&mut host).await else {
+        panic!("expected the game to start");
+    };
+    assert_eq!(opponent, "guest");
+    let ClientBoundPacket::GameStart { opponent, .. } = recv(&mut guest).await else {
+        panic!("expected the game to start");
+    };
+    assert_eq!(opponent, "host");
+
+    // the room is gone once the game starts
+    let (mut late, mut late_writer) = join(63941, "late").await;
+    late_writer
+        .send(ServerBoundPacket::JoinRoom { code })
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv(&mut late).await,
+        ClientBoundPacket::Error {
+            code: ErrorCode::UnknownRoom,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_spectator() {
+    let _server = common::Server::start(64941);
+    let (mut host, mut host_writer) = join(64941, "host").await;
+    host_writer
+        .send(ServerBoundPacket::CreateRoom)
+        .await
+        .unwrap();
+    let ClientBoundPacket::RoomCreated { code } = recv(&mut host).await else {
+        panic!("expected a join code");
+    };
+    let (_guest, mut guest_writer) = join(64941, "guest").await;
+    guest_writer
+        .send(ServerBoundPacket::JoinRoom { code })
+        .await
+        .unwrap();
+    let ClientBoundPacket::GameStart { your_color, .. } = recv(&mut host).await else {
+        panic!("expected the game to start");
+    };
+    let red_writer = match your_color {
+        Color::Red => &mut host_writer,
+        Color::Yellow => &mut guest_writer,
+    };
+
+    // only clients that asked for spectating get to use it
+    let (mut outsider, mut outsider_writer) = join(64941, "outsider").await;
+    outsider_writer
+        .send(ServerBoundPacket::ListGames)
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv(&mut outsider).await,
+        ClientBoundPacket::Error {
+            code: ErrorCode::UnexpectedPacket,
+            ..
+        }
+    ));
+
+    let (mut watcher, mut watcher_writer) = connect(64941).await;
+    watcher_writer
+        .send(init_with("watcher", Capabilities::SPECTATE))
+        .await
+        .unwrap();
+    assert_eq!(
+        recv(&mut watcher).await,
+        ClientBoundPacket::Welcome {
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::SPECTATE,
+        }
+    );
+    watcher_writer
+        .send(ServerBoundPacket::ListGames)
+        .await
+        .unwrap();
+    let ClientBoundPacket::GameList { games } = recv(&mut watcher).await else {
+        panic!("expected the list of games");
+    };
+    let [game] = &games[..] else {
+        panic!("expected one game, got {:?}", games);
+    };
+    let mut names = [game.red.as_str(), game.yellow.as_str()];
+    names.sort();
+    assert_eq!(names, ["guest", "host"]);
+
+    watcher_writer
+        .send(ServerBoundPacket::Watch { id: game.id + 1 })
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv(&mut watcher).await,
+        ClientBoundPacket::Error {
+            code: ErrorCode::UnknownGame,
+            ..
+        }
+    ));
+    watcher_writer
+        .send(ServerBoundPacket::Watch { id: game.id })
+        .await
+        .unwrap();
+    let ClientBoundPacket::State { move_number, .. } = recv(&mut watcher).await else {
+        panic!("expected the game's state");
+    };
+    assert_eq!(move_number, 0);
+
+    red_writer
+        .send(ServerBoundPacket::Move { col: 3 })
+        .await
+        .unwrap();
+    assert_eq!(
+        recv(&mut watcher).await,
+        ClientBoundPacket::Move {
+            col: 3,
+            color: Color::Red,
+        }
+    );
+
+    // spectators cannot play
+    watcher_writer
+        .send(ServerBoundPacket::Move { col: 4 })
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv(&mut watcher).await,
+        ClientBoundPacket::Error {
+            code: ErrorCode::UnexpectedPacket,
+            ..
+        }
+    ));
+
+    red_writer.send(ServerBoundPacket::Forfeit).await.unwrap();
+    assert!(matches!(
+        recv(&mut watcher).await,
+        ClientBoundPacket::GameResult {
+            result: GameResult::YellowWin,
+            reason: EndReason::Forfeit,
+            ..
+        }
+    ));
+}
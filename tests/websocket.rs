@@ -0,0 +1,121 @@
+mod common;
+
+use common::{connect, init, Server};
+use connect_4::{
+    Capabilities, ClientBoundPacket, Color, Deserialize, EndReason, GameResult, Serialize,
+    ServerBoundPacket, PROTOCOL_VERSION,
+};
+use futures_util::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect_ws(port: u16) -> WebSocket {
+    // the server may still be binding its listeners
+    for _ in 0..50 {
+        if let Ok((socket, _)) = connect_async(format!("ws://127.0.0.1:{}", port)).await {
+            return socket;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("could not connect to the websocket listener");
+}
+
+async fn send_ws(socket: &mut WebSocket, packet: ServerBoundPacket) {
+    socket
+        .send(Message::Binary(packet.serialize()))
+        .await
+        .unwrap();
+}
+
+async fn recv_ws(socket: &mut WebSocket) -> ClientBoundPacket {
+    loop {
+        match socket.next().await.unwrap().unwrap() {
+            Message::Binary(buf) => return ClientBoundPacket::deserialize(&buf).unwrap(),
+            Message::Ping(_) | Message::Pong(_) => continue,
+            message => panic!("unexpected websocket message {:?}", message),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_websocket_player_against_tcp_player() {
+    let _server = Server::start(61941);
+    let mut browser = connect_ws(61943).await;
+    send_ws(&mut browser, init("browser")).await;
+    send_ws(&mut browser, ServerBoundPacket::Queue).await;
+    assert_eq!(
+        recv_ws(&mut browser).await,
+        ClientBoundPacket::Welcome {
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::VARIANTS,
+        }
+    );
+    assert_eq!(
+        recv_ws(&mut browser).await,
+        ClientBoundPacket::QueuePosition { position: 1 }
+    );
+
+    let (mut reader, mut writer) = connect(61941).await;
+    writer.send(init("terminal")).await.unwrap();
+    writer.send(ServerBoundPacket::Queue).await.unwrap();
+    assert!(matches!(
+        reader.read().await.unwrap(),
+        Ok(ClientBoundPacket::Welcome { .. })
+    ));
+
+    // the two transports are paired with each other
+    let ClientBoundPacket::GameStart {
+        opponent,
+        your_color,
+        ..
+    } = recv_ws(&mut browser).await
+    else {
+        panic!("expected the game to start");
+    };
+    assert_eq!(opponent, "terminal");
+    let Ok(ClientBoundPacket::GameStart { opponent, .. }) = reader.read().await.unwrap() else {
+        panic!("expected the game to start");
+    };
+    assert_eq!(opponent, "browser");
+    assert!(matches!(
+        recv_ws(&mut browser).await,
+        ClientBoundPacket::State { .. }
+    ));
+    assert!(matches!(
+        reader.read().await.unwrap(),
+        Ok(ClientBoundPacket::State { .. })
+    ));
+
+    if your_color == Color::Red {
+        send_ws(&mut browser, ServerBoundPacket::Move { col: 3 }).await;
+    } else {
+        writer
+            .send(ServerBoundPacket::Move { col: 3 })
+            .await
+            .unwrap();
+    }
+    let moved = ClientBoundPacket::Move {
+        col: 3,
+        color: Color::Red,
+    };
+    assert_eq!(recv_ws(&mut browser).await, moved);
+    assert_eq!(reader.read().await.unwrap(), Ok(moved));
+
+    send_ws(&mut browser, ServerBoundPacket::Forfeit).await;
+    let result = ClientBoundPacket::GameResult {
+        result: match your_color {
+            Color::Red => GameResult::YellowWin,
+            Color::Yellow => GameResult::RedWin,
+        },
+        col: None,
+        color: your_color,
+        reason: EndReason::Forfeit,
+        lines: Vec::new(),
+    };
+    assert_eq!(recv_ws(&mut browser).await, result);
+    assert_eq!(reader.read().await.unwrap(), Ok(result));
+}